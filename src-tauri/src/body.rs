@@ -0,0 +1,335 @@
+use base64::Engine;
+use serde::Serialize;
+use serde_json::Value;
+
+/// Response body as returned to the frontend, together with what we know
+/// about its media type.
+#[derive(Serialize)]
+pub struct ResponseBody {
+    /// Media type without parameters, either from `Content-Type` or sniffed.
+    pub content_type: Option<String>,
+    /// Charset the text was decoded from, when the body is textual.
+    pub charset: Option<String>,
+    /// Size of the whole body, even when only a preview is included.
+    pub size_bytes: u64,
+    /// Whether `size_bytes` only counts what was read before the rest of a
+    /// body of unknown length was dropped; the whole body is larger.
+    pub size_is_lower_bound: bool,
+    /// Whether the content is only the start of the body.
+    pub truncated: bool,
+    #[serde(flatten)]
+    pub content: BodyContent,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum BodyContent {
+    Empty,
+    Json { value: Value },
+    Text { text: String },
+    Binary { base64: String },
+}
+
+#[derive(PartialEq, Debug)]
+enum BodyClass {
+    Json,
+    Text,
+    Binary,
+}
+
+impl ResponseBody {
+    /// Classifies raw body bytes using the `Content-Type` header first and
+    /// falling back to content sniffing when the header is missing or vague.
+    pub fn from_bytes(content_type_header: Option<&str>, bytes: &[u8]) -> Self {
+        let (declared_type, declared_charset) = match content_type_header {
+            Some(header) => parse_content_type(header),
+            None => (None, None),
+        };
+
+        if bytes.is_empty() {
+            return ResponseBody {
+                content_type: declared_type,
+                charset: declared_charset,
+                size_bytes: 0,
+                size_is_lower_bound: false,
+                truncated: false,
+                content: BodyContent::Empty,
+            };
+        }
+
+        // Trust specific declarations, sniff generic or missing ones
+        let declared_class = declared_type.as_deref().and_then(classify_media_type);
+        let (content_type, class) = match declared_class {
+            Some(class) => (declared_type, class),
+            None => {
+                let (sniffed_type, class) = sniff(bytes);
+                (declared_type.or(sniffed_type), class)
+            }
+        };
+
+        let (content, charset) = match class {
+            BodyClass::Binary => (binary(bytes), None),
+            BodyClass::Json | BodyClass::Text => {
+                match decode_text(bytes, declared_charset.as_deref()) {
+                    Some((text, charset)) => {
+                        (textual(text, class == BodyClass::Json), Some(charset))
+                    }
+                    None => (binary(bytes), None),
+                }
+            }
+        };
+
+        ResponseBody {
+            content_type,
+            charset,
+            size_bytes: bytes.len() as u64,
+            size_is_lower_bound: false,
+            truncated: false,
+            content,
+        }
+    }
+
+    /// Describes a body of `size` bytes from its first bytes. The preview is
+    /// classified like a full body, so cut-off JSON shows up as text.
+    pub fn preview(content_type_header: Option<&str>, bytes: &[u8], size: u64) -> Self {
+        let truncated = size > bytes.len() as u64;
+        // Drop a UTF-8 sequence split by the cut so text still decodes
+        let end = match std::str::from_utf8(bytes) {
+            Err(e) if truncated && e.error_len().is_none() => e.valid_up_to(),
+            _ => bytes.len(),
+        };
+        ResponseBody {
+            size_bytes: size,
+            truncated,
+            ..ResponseBody::from_bytes(content_type_header, &bytes[..end])
+        }
+    }
+}
+
+/// Keeps JSON-declared bodies structured when they parse, text otherwise.
+fn textual(text: String, as_json: bool) -> BodyContent {
+    if as_json {
+        if let Ok(value) = serde_json::from_str(&text) {
+            return BodyContent::Json { value };
+        }
+    }
+    BodyContent::Text { text }
+}
+
+fn binary(bytes: &[u8]) -> BodyContent {
+    BodyContent::Binary {
+        base64: base64::engine::general_purpose::STANDARD.encode(bytes),
+    }
+}
+
+/// Splits a `Content-Type` header into its lowercased essence and charset.
+fn parse_content_type(header: &str) -> (Option<String>, Option<String>) {
+    let mut parts = header.split(';');
+    let essence = parts
+        .next()
+        .map(|s| s.trim().to_ascii_lowercase())
+        .filter(|s| !s.is_empty());
+    let charset = parts.find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        if name.trim().eq_ignore_ascii_case("charset") {
+            Some(value.trim().trim_matches('"').to_ascii_lowercase())
+        } else {
+            None
+        }
+    });
+    (essence, charset)
+}
+
+/// Maps a media type to a body class, or `None` when it says nothing useful.
+fn classify_media_type(media_type: &str) -> Option<BodyClass> {
+    if media_type == "application/octet-stream" {
+        return None;
+    }
+    if media_type == "application/json" || media_type.ends_with("+json") {
+        return Some(BodyClass::Json);
+    }
+    if media_type.starts_with("text/")
+        || media_type.ends_with("+xml")
+        || matches!(
+            media_type,
+            "application/xml"
+                | "application/javascript"
+                | "application/ecmascript"
+                | "application/x-www-form-urlencoded"
+                | "application/graphql"
+                | "application/yaml"
+                | "application/x-yaml"
+                | "application/toml"
+                | "application/x-ndjson"
+        )
+    {
+        return Some(BodyClass::Text);
+    }
+    Some(BodyClass::Binary)
+}
+
+/// Guesses the body class from its leading bytes.
+fn sniff(bytes: &[u8]) -> (Option<String>, BodyClass) {
+    const SIGNATURES: &[(&[u8], &str)] = &[
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"%PDF-", "application/pdf"),
+        (b"PK\x03\x04", "application/zip"),
+        (b"\x1f\x8b", "application/gzip"),
+        (b"\x00asm", "application/wasm"),
+    ];
+    for (magic, media_type) in SIGNATURES {
+        if bytes.starts_with(magic) {
+            return (Some(media_type.to_string()), BodyClass::Binary);
+        }
+    }
+    if bytes.len() >= 12 && &bytes[0..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+        return (Some("image/webp".to_string()), BodyClass::Binary);
+    }
+
+    let text = match std::str::from_utf8(bytes) {
+        Ok(text) if !text.contains('\0') => text,
+        _ => return (None, BodyClass::Binary),
+    };
+    let trimmed = text.trim_start();
+    if (trimmed.starts_with('{') || trimmed.starts_with('['))
+        && serde_json::from_str::<Value>(text).is_ok()
+    {
+        return (Some("application/json".to_string()), BodyClass::Json);
+    }
+    let lowered: String = trimmed
+        .chars()
+        .take(64)
+        .collect::<String>()
+        .to_ascii_lowercase();
+    if lowered.starts_with("<!doctype html") || lowered.starts_with("<html") {
+        return (Some("text/html".to_string()), BodyClass::Text);
+    }
+    if lowered.starts_with("<?xml") {
+        return (Some("application/xml".to_string()), BodyClass::Text);
+    }
+    (Some("text/plain".to_string()), BodyClass::Text)
+}
+
+/// Decodes text in the declared charset, defaulting to UTF-8. Returns `None`
+/// when the bytes are not valid in that charset.
+fn decode_text(bytes: &[u8], charset: Option<&str>) -> Option<(String, String)> {
+    let encoding = charset
+        .and_then(|label| encoding_rs::Encoding::for_label(label.as_bytes()))
+        .unwrap_or(encoding_rs::UTF_8);
+    let (text, had_errors) = encoding.decode_without_bom_handling(bytes);
+    if had_errors {
+        return None;
+    }
+    Some((text.into_owned(), encoding.name().to_ascii_lowercase()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kind(body: &ResponseBody) -> &'static str {
+        match body.content {
+            BodyContent::Empty => "empty",
+            BodyContent::Json { .. } => "json",
+            BodyContent::Text { .. } => "text",
+            BodyContent::Binary { .. } => "binary",
+        }
+    }
+
+    #[test]
+    fn sniffs_signatures_and_text() {
+        assert_eq!(
+            sniff(b"\x89PNG\r\n\x1a\n...."),
+            (Some("image/png".to_string()), BodyClass::Binary)
+        );
+        assert_eq!(
+            sniff(b"RIFF\0\0\0\0WEBPVP8 "),
+            (Some("image/webp".to_string()), BodyClass::Binary)
+        );
+        assert_eq!(
+            sniff(b"  {\"a\": [1, 2]}"),
+            (Some("application/json".to_string()), BodyClass::Json)
+        );
+        // Looks like JSON but does not parse
+        assert_eq!(
+            sniff(b"{not json"),
+            (Some("text/plain".to_string()), BodyClass::Text)
+        );
+        assert_eq!(
+            sniff(b"<!DOCTYPE html><html></html>"),
+            (Some("text/html".to_string()), BodyClass::Text)
+        );
+        assert_eq!(
+            sniff(b"<?xml version=\"1.0\"?><a/>"),
+            (Some("application/xml".to_string()), BodyClass::Text)
+        );
+        assert_eq!(sniff(b"text\0with nul"), (None, BodyClass::Binary));
+        assert_eq!(sniff(b"\xff\xfe invalid"), (None, BodyClass::Binary));
+    }
+
+    #[test]
+    fn classifies_media_types() {
+        assert_eq!(
+            classify_media_type("application/json"),
+            Some(BodyClass::Json)
+        );
+        assert_eq!(
+            classify_media_type("application/problem+json"),
+            Some(BodyClass::Json)
+        );
+        assert_eq!(classify_media_type("text/csv"), Some(BodyClass::Text));
+        assert_eq!(classify_media_type("image/svg+xml"), Some(BodyClass::Text));
+        assert_eq!(classify_media_type("image/png"), Some(BodyClass::Binary));
+        assert_eq!(classify_media_type("application/octet-stream"), None);
+    }
+
+    #[test]
+    fn parses_content_type_parameters() {
+        assert_eq!(
+            parse_content_type("Text/HTML; Charset=\"ISO-8859-1\""),
+            (
+                Some("text/html".to_string()),
+                Some("iso-8859-1".to_string())
+            )
+        );
+        assert_eq!(parse_content_type(";charset=utf-8").0, None);
+    }
+
+    #[test]
+    fn declared_type_wins_over_sniffing() {
+        let body = ResponseBody::from_bytes(Some("text/plain"), b"{\"a\": 1}");
+        assert_eq!(kind(&body), "text");
+        let body = ResponseBody::from_bytes(Some("application/octet-stream"), b"{\"a\": 1}");
+        assert_eq!(kind(&body), "json");
+        assert_eq!(
+            body.content_type.as_deref(),
+            Some("application/octet-stream")
+        );
+        // Invalid JSON declared as JSON is still shown
+        let body = ResponseBody::from_bytes(Some("application/json"), b"{oops");
+        assert_eq!(kind(&body), "text");
+    }
+
+    #[test]
+    fn decodes_declared_charsets() {
+        let body = ResponseBody::from_bytes(Some("text/plain; charset=iso-8859-1"), b"caf\xe9");
+        assert_eq!(body.charset.as_deref(), Some("windows-1252"));
+        assert!(matches!(&body.content, BodyContent::Text { text } if text == "café"));
+        // Not valid in the declared charset
+        let body = ResponseBody::from_bytes(Some("text/plain; charset=utf-8"), b"caf\xe9");
+        assert_eq!(kind(&body), "binary");
+    }
+
+    #[test]
+    fn previews_cut_inside_a_utf8_sequence() {
+        let text = "héllo".as_bytes();
+        let body = ResponseBody::preview(Some("text/plain"), &text[..2], 6);
+        assert!(body.truncated);
+        assert_eq!(body.size_bytes, 6);
+        assert!(matches!(&body.content, BodyContent::Text { text } if text == "h"));
+        assert_eq!(kind(&ResponseBody::from_bytes(None, b"")), "empty");
+    }
+}
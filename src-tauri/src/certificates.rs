@@ -0,0 +1,449 @@
+use crate::error::{FetchError, FetchErrorKind};
+use crate::options::RequestOptions;
+use crate::{storage, tls};
+use base64::Engine;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use rustls::RootCertStore;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::{Mutex, MutexGuard};
+use url::Url;
+
+/// CA bundles and client identities registered by the user. They are kept
+/// with the app data, key material and PKCS#12 passwords included, and
+/// matched to requests by host.
+#[derive(Default)]
+pub struct CertificateStore {
+    file: Option<PathBuf>,
+    entries: Mutex<Vec<Certificate>>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Certificate {
+    pub id: String,
+    pub name: String,
+    /// `example.com` for one host, `*.example.com` for its subdomains, or
+    /// `*` for every host.
+    pub host_pattern: String,
+    pub material: CertificateMaterial,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum CertificateMaterial {
+    /// Extra trusted roots, added to the built-in ones.
+    CaBundle {
+        pem: String,
+    },
+    PemIdentity {
+        cert_pem: String,
+        key_pem: String,
+    },
+    Pkcs12Identity {
+        der_base64: String,
+        password: String,
+    },
+}
+
+/// A certificate to register, read from files on disk.
+#[derive(Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum NewCertificate {
+    CaBundle {
+        name: String,
+        host_pattern: String,
+        path: String,
+    },
+    PemIdentity {
+        name: String,
+        host_pattern: String,
+        cert_path: String,
+        key_path: String,
+    },
+    Pkcs12Identity {
+        name: String,
+        host_pattern: String,
+        path: String,
+        #[serde(default)]
+        password: String,
+    },
+}
+
+/// What the frontend sees of a registered certificate; never key material.
+#[derive(Serialize)]
+pub struct CertificateSummary {
+    id: String,
+    name: String,
+    host_pattern: String,
+    kind: &'static str,
+}
+
+/// Parsed material, ready to hand to rustls.
+pub enum TlsMaterial {
+    Roots(Vec<CertificateDer<'static>>),
+    Identity(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>),
+}
+
+impl CertificateStore {
+    /// Loads the certificates saved in `file`, starting empty when it is
+    /// missing or unreadable.
+    pub fn load(file: PathBuf) -> Self {
+        CertificateStore {
+            entries: Mutex::new(storage::read_json(&file).unwrap_or_default()),
+            file: Some(file),
+        }
+    }
+
+    pub fn list(&self) -> Vec<CertificateSummary> {
+        self.lock().iter().map(Certificate::summary).collect()
+    }
+
+    /// Reads and validates the files, then stores their contents so the
+    /// originals may be moved or deleted afterwards.
+    pub fn add(&self, new: NewCertificate) -> Result<CertificateSummary, FetchError> {
+        let (name, host_pattern, material) = match new {
+            NewCertificate::CaBundle {
+                name,
+                host_pattern,
+                path,
+            } => (
+                name,
+                host_pattern,
+                CertificateMaterial::CaBundle {
+                    pem: read_text(&path)?,
+                },
+            ),
+            NewCertificate::PemIdentity {
+                name,
+                host_pattern,
+                cert_path,
+                key_path,
+            } => (
+                name,
+                host_pattern,
+                CertificateMaterial::PemIdentity {
+                    cert_pem: read_text(&cert_path)?,
+                    key_pem: read_text(&key_path)?,
+                },
+            ),
+            NewCertificate::Pkcs12Identity {
+                name,
+                host_pattern,
+                path,
+                password,
+            } => (
+                name,
+                host_pattern,
+                CertificateMaterial::Pkcs12Identity {
+                    der_base64: base64::engine::general_purpose::STANDARD
+                        .encode(read_bytes(&path)?),
+                    password,
+                },
+            ),
+        };
+
+        let host_pattern = host_pattern.trim().to_ascii_lowercase();
+        if host_pattern.is_empty() {
+            return Err(invalid("Host pattern cannot be empty".to_string()));
+        }
+        let certificate = Certificate {
+            id: String::new(),
+            name,
+            host_pattern,
+            material,
+        };
+        // Building a TLS configuration also checks that a key fits its certificate
+        tls::client_config(
+            &RequestOptions::default(),
+            std::slice::from_ref(&certificate),
+        )?;
+
+        let mut entries = self.lock();
+        let id = loop {
+            let id = format!("{:016x}", fastrand::u64(..));
+            if entries.iter().all(|existing| existing.id != id) {
+                break id;
+            }
+        };
+        let certificate = Certificate { id, ..certificate };
+        let summary = certificate.summary();
+        entries.push(certificate);
+        self.save(&entries)?;
+        Ok(summary)
+    }
+
+    /// Removes a certificate. Returns false when no such ID is registered.
+    pub fn remove(&self, id: &str) -> Result<bool, FetchError> {
+        let mut entries = self.lock();
+        let before = entries.len();
+        entries.retain(|certificate| certificate.id != id);
+        if entries.len() == before {
+            return Ok(false);
+        }
+        self.save(&entries)?;
+        Ok(true)
+    }
+
+    /// The certificates that apply to requests for `url`: every matching CA
+    /// bundle and the most specific matching client identity.
+    pub fn for_url(&self, url: &str) -> Vec<Certificate> {
+        let Some(host) = Url::parse(url.trim()).ok().and_then(|url| {
+            url.host_str()
+                .map(|host| host.trim_matches(['[', ']']).to_string())
+        }) else {
+            return Vec::new();
+        };
+
+        let entries = self.lock();
+        let matching = entries.iter().filter_map(|certificate| {
+            Some((host_match(&certificate.host_pattern, &host)?, certificate))
+        });
+        let mut selected: Vec<Certificate> = Vec::new();
+        let mut identity: Option<(usize, &Certificate)> = None;
+        for (specificity, certificate) in matching {
+            if matches!(certificate.material, CertificateMaterial::CaBundle { .. }) {
+                selected.push(certificate.clone());
+            } else if identity.is_none_or(|(best, _)| specificity > best) {
+                identity = Some((specificity, certificate));
+            }
+        }
+        selected.extend(identity.map(|(_, certificate)| certificate.clone()));
+        selected
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<Certificate>> {
+        self.entries
+            .lock()
+            .expect("certificate store lock poisoned")
+    }
+
+    fn save(&self, entries: &[Certificate]) -> Result<(), FetchError> {
+        match &self.file {
+            Some(file) => storage::write_json(file, &entries),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Certificate {
+    fn summary(&self) -> CertificateSummary {
+        CertificateSummary {
+            id: self.id.clone(),
+            name: self.name.clone(),
+            host_pattern: self.host_pattern.clone(),
+            kind: match self.material {
+                CertificateMaterial::CaBundle { .. } => "ca_bundle",
+                CertificateMaterial::PemIdentity { .. } => "pem_identity",
+                CertificateMaterial::Pkcs12Identity { .. } => "pkcs12_identity",
+            },
+        }
+    }
+}
+
+impl CertificateMaterial {
+    pub fn parse(&self) -> Result<TlsMaterial, FetchError> {
+        match self {
+            CertificateMaterial::CaBundle { pem } => {
+                let roots = parse_certificates(pem)?;
+                // Reject certificates rustls cannot use as trust anchors
+                let mut store = RootCertStore::empty();
+                for root in &roots {
+                    store
+                        .add(root.clone())
+                        .map_err(|e| invalid(format!("Unusable CA certificate: {}", e)))?;
+                }
+                Ok(TlsMaterial::Roots(roots))
+            }
+            CertificateMaterial::PemIdentity { cert_pem, key_pem } => {
+                let key = PrivateKeyDer::from_pem_slice(key_pem.as_bytes()).map_err(|e| {
+                    invalid(format!("No usable private key in the key file: {}", e))
+                })?;
+                Ok(TlsMaterial::Identity(parse_certificates(cert_pem)?, key))
+            }
+            CertificateMaterial::Pkcs12Identity {
+                der_base64,
+                password,
+            } => {
+                let der = base64::engine::general_purpose::STANDARD
+                    .decode(der_base64)
+                    .map_err(|e| invalid(format!("Corrupt PKCS#12 data: {}", e)))?;
+                let store = p12_keystore::KeyStore::from_pkcs12(
+                    &der,
+                    password,
+                    p12_keystore::Pkcs12ImportPolicy::Strict,
+                )
+                .map_err(|e| invalid(format!("Could not open PKCS#12 file: {}", e)))?;
+                let (_, chain) = store
+                    .private_key_chain()
+                    .ok_or_else(|| invalid("PKCS#12 file contains no private key".to_string()))?;
+                let certs = chain
+                    .certs()
+                    .iter()
+                    .map(|cert| CertificateDer::from(cert.as_der().to_vec()))
+                    .collect();
+                let key = PrivatePkcs8KeyDer::from(chain.key().as_der().to_vec());
+                Ok(TlsMaterial::Identity(certs, key.into()))
+            }
+        }
+    }
+}
+
+/// How specifically `pattern` matches `host`, or `None` when it does not.
+fn host_match(pattern: &str, host: &str) -> Option<usize> {
+    let host = host.to_ascii_lowercase();
+    if pattern == "*" {
+        return Some(0);
+    }
+    if let Some(suffix) = pattern.strip_prefix("*.") {
+        let matches = host.len() > suffix.len()
+            && host.ends_with(suffix)
+            && host[..host.len() - suffix.len()].ends_with('.');
+        return matches.then_some(suffix.len());
+    }
+    (pattern == host).then_some(usize::MAX)
+}
+
+fn parse_certificates(pem: &str) -> Result<Vec<CertificateDer<'static>>, FetchError> {
+    let certificates = CertificateDer::pem_slice_iter(pem.as_bytes())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| invalid(format!("Invalid PEM certificate: {}", e)))?;
+    if certificates.is_empty() {
+        return Err(invalid("No PEM certificates found".to_string()));
+    }
+    Ok(certificates)
+}
+
+fn read_text(path: &str) -> Result<String, FetchError> {
+    String::from_utf8(read_bytes(path)?)
+        .map_err(|_| invalid(format!("{} is not a PEM text file", path)))
+}
+
+fn read_bytes(path: &str) -> Result<Vec<u8>, FetchError> {
+    std::fs::read(path).map_err(|e| {
+        FetchError::with_context(
+            FetchErrorKind::InvalidCertificate,
+            format_args!("Failed to read {}", path),
+            &e,
+        )
+    })
+}
+
+fn invalid(message: String) -> FetchError {
+    FetchError::new(FetchErrorKind::InvalidCertificate, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{temp_file, CA_KEY_PEM, CA_PEM};
+
+    fn ca_bundle(host_pattern: &str) -> NewCertificate {
+        NewCertificate::CaBundle {
+            name: "CA".to_string(),
+            host_pattern: host_pattern.to_string(),
+            path: temp_file(CA_PEM),
+        }
+    }
+
+    fn identity(name: &str, host_pattern: &str) -> NewCertificate {
+        NewCertificate::PemIdentity {
+            name: name.to_string(),
+            host_pattern: host_pattern.to_string(),
+            cert_path: temp_file(CA_PEM),
+            key_path: temp_file(CA_KEY_PEM),
+        }
+    }
+
+    fn names(certificates: &[Certificate]) -> Vec<&str> {
+        certificates.iter().map(|cert| cert.name.as_str()).collect()
+    }
+
+    #[test]
+    fn exact_patterns_match_only_that_host() {
+        assert_eq!(
+            host_match("api.example.com", "api.example.com"),
+            Some(usize::MAX)
+        );
+        assert_eq!(
+            host_match("api.example.com", "API.Example.com"),
+            Some(usize::MAX)
+        );
+        assert_eq!(host_match("api.example.com", "example.com"), None);
+        assert_eq!(host_match("api.example.com", "x.api.example.com"), None);
+    }
+
+    #[test]
+    fn wildcards_match_subdomains_only() {
+        assert_eq!(host_match("*.example.com", "a.example.com"), Some(11));
+        assert_eq!(host_match("*.example.com", "a.b.example.com"), Some(11));
+        assert_eq!(host_match("*.example.com", "example.com"), None);
+        assert_eq!(host_match("*.example.com", "badexample.com"), None);
+        assert_eq!(host_match("*.example.com", ".example.com"), Some(11));
+        assert_eq!(host_match("*", "anything.test"), Some(0));
+    }
+
+    #[test]
+    fn ports_do_not_affect_matching() {
+        let store = CertificateStore::default();
+        store.add(ca_bundle("internal.corp")).unwrap();
+        store.add(ca_bundle("::1")).unwrap();
+        assert_eq!(store.for_url("https://internal.corp:8443/x").len(), 1);
+        assert_eq!(store.for_url("https://internal.corp/x").len(), 1);
+        assert_eq!(store.for_url("https://[::1]:8443/").len(), 1);
+        assert!(store.for_url("https://other.corp:8443/").is_empty());
+        assert!(store.for_url("not a url").is_empty());
+    }
+
+    #[test]
+    fn selects_every_ca_and_the_most_specific_identity() {
+        let store = CertificateStore::default();
+        store.add(identity("any", "*")).unwrap();
+        store.add(identity("corp", "*.internal.corp")).unwrap();
+        store.add(identity("api", "api.internal.corp")).unwrap();
+        store.add(ca_bundle("*.internal.corp")).unwrap();
+
+        assert_eq!(
+            names(&store.for_url("https://api.internal.corp/")),
+            ["CA", "api"]
+        );
+        assert_eq!(
+            names(&store.for_url("https://db.internal.corp/")),
+            ["CA", "corp"]
+        );
+        assert_eq!(names(&store.for_url("https://example.com/")), ["any"]);
+    }
+
+    #[test]
+    fn ids_are_unique_and_removable() {
+        let store = CertificateStore::default();
+        let ids: Vec<String> = (0..20)
+            .map(|_| store.add(ca_bundle("example.com")).unwrap().id)
+            .collect();
+        let mut unique = ids.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), ids.len());
+
+        assert!(store.remove(&ids[0]).unwrap());
+        assert!(!store.remove(&ids[0]).unwrap());
+        assert_eq!(store.list().len(), 19);
+    }
+
+    #[test]
+    fn rejects_bad_material() {
+        let store = CertificateStore::default();
+        let kind = |new| store.add(new).err().map(|err| err.kind);
+        assert_eq!(
+            kind(ca_bundle("  ")),
+            Some(FetchErrorKind::InvalidCertificate)
+        );
+        let not_pem = NewCertificate::CaBundle {
+            name: "bad".to_string(),
+            host_pattern: "example.com".to_string(),
+            path: temp_file("hello"),
+        };
+        assert_eq!(kind(not_pem), Some(FetchErrorKind::InvalidCertificate));
+        assert!(store.list().is_empty());
+    }
+}
@@ -0,0 +1,488 @@
+use crate::certificates::{Certificate, CertificateStore};
+use crate::cookies::CookieJars;
+use crate::error::{FetchError, FetchErrorKind};
+use crate::options::{DnsOverride, HttpVersion, RequestOptions};
+use crate::proxy::{self, ProxySettings};
+use crate::{dns, timing, tls};
+use reqwest::redirect::Policy;
+use reqwest::ClientBuilder;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use url::Url;
+
+/// Long-lived clients shared between requests, one per distinct set of
+/// client-level options, so connections, TLS sessions and DNS results are
+/// reused the way a real client would reuse them.
+#[derive(Default)]
+pub struct ClientPool {
+    clients: Mutex<HashMap<ClientKey, reqwest::Client>>,
+}
+
+/// The subset of request options that is baked into a `reqwest::Client`.
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct ClientKey {
+    connect_timeout_ms: Option<u64>,
+    read_timeout_ms: Option<u64>,
+    http_version: HttpVersion,
+    accept_invalid_certs: bool,
+    cookie_workspace: Option<String>,
+    proxy: ProxySettings,
+    dns_overrides: Vec<DnsOverride>,
+    dns_server: Option<String>,
+    unix_socket: Option<String>,
+    certificate_ids: Vec<String>,
+}
+
+impl ClientKey {
+    fn new(options: &RequestOptions, certificates: &[Certificate]) -> Result<Self, FetchError> {
+        Ok(ClientKey {
+            connect_timeout_ms: options.connect_timeout_ms,
+            read_timeout_ms: options.read_timeout_ms,
+            http_version: options.http_version,
+            accept_invalid_certs: options.accept_invalid_certs,
+            cookie_workspace: options.cookie_workspace().map(str::to_string),
+            proxy: options.proxy.clone(),
+            dns_overrides: options.normalized_dns_overrides(),
+            dns_server: options.normalized_dns_server(),
+            unix_socket: options.unix_socket_path()?.map(str::to_string),
+            certificate_ids: certificates.iter().map(|cert| cert.id.clone()).collect(),
+        })
+    }
+}
+
+impl ClientPool {
+    /// Returns the shared client for these options, or a throwaway one when
+    /// the request asks for a fresh connection.
+    pub fn client_for(
+        &self,
+        options: &RequestOptions,
+        cookies: &Arc<CookieJars>,
+        certificates: &[Certificate],
+    ) -> Result<reqwest::Client, FetchError> {
+        if options.fresh_connection {
+            return build_client(options, cookies, certificates);
+        }
+
+        let mut clients = self.clients.lock().expect("client pool lock poisoned");
+        let key = ClientKey::new(options, certificates)?;
+        if let Some(client) = clients.get(&key) {
+            return Ok(client.clone());
+        }
+        let client = build_client(options, cookies, certificates)?;
+        clients.insert(key, client.clone());
+        Ok(client)
+    }
+
+    /// Drops every pooled client and with them all idle connections.
+    /// Returns how many clients were discarded.
+    pub fn reset(&self) -> usize {
+        let mut clients = self.clients.lock().expect("client pool lock poisoned");
+        let count = clients.len();
+        clients.clear();
+        count
+    }
+}
+
+/// The clients one request goes through. Certificates are selected by host,
+/// so each redirect hop gets a client with the CA bundles and identity
+/// registered for its own host, never those of the host it came from.
+pub struct RequestClients {
+    pool: Arc<ClientPool>,
+    cookies: Arc<CookieJars>,
+    certificates: Arc<CertificateStore>,
+    options: RequestOptions,
+    /// The last client handed out and the certificates it was built with,
+    /// kept so a fresh-connection request reuses its client across hops and
+    /// attempts that need the same certificates.
+    current: Mutex<Option<(Vec<String>, reqwest::Client)>>,
+}
+
+impl RequestClients {
+    pub fn new(
+        pool: Arc<ClientPool>,
+        cookies: Arc<CookieJars>,
+        certificates: Arc<CertificateStore>,
+        options: RequestOptions,
+    ) -> Self {
+        RequestClients {
+            pool,
+            cookies,
+            certificates,
+            options,
+            current: Mutex::new(None),
+        }
+    }
+
+    /// The client to send a request for `url` with.
+    pub fn for_url(&self, url: &Url) -> Result<reqwest::Client, FetchError> {
+        let certificates = self.certificates.for_url(url.as_str());
+        let ids: Vec<String> = certificates.iter().map(|cert| cert.id.clone()).collect();
+        let mut current = self.current.lock().expect("request client lock poisoned");
+        if let Some((current_ids, client)) = current.as_ref() {
+            if *current_ids == ids {
+                return Ok(client.clone());
+            }
+        }
+        let client = self
+            .pool
+            .client_for(&self.options, &self.cookies, &certificates)?;
+        *current = Some((ids, client.clone()));
+        Ok(client)
+    }
+}
+
+/// Builds a client configured for the given request options.
+fn build_client(
+    options: &RequestOptions,
+    cookies: &Arc<CookieJars>,
+    certificates: &[Certificate],
+) -> Result<reqwest::Client, FetchError> {
+    let mut builder = reqwest::Client::builder()
+        .use_preconfigured_tls(tls::client_config(options, certificates)?)
+        .dns_resolver(Arc::new(dns::Resolver::new(options)?))
+        .connector_layer(timing::ConnectTimingLayer)
+        // Redirects are followed hop by hop in `redirect` so each is recorded
+        .redirect(Policy::none());
+
+    builder = proxy::apply(builder, &options.proxy)?;
+    if let Some(path) = options.unix_socket_path()? {
+        builder = unix_socket(builder, path)?;
+    }
+    if let Some(workspace) = options.cookie_workspace() {
+        builder = builder.cookie_provider(Arc::new(cookies.jar(workspace)));
+    }
+    if let Some(timeout) = options.connect_timeout() {
+        builder = builder.connect_timeout(timeout);
+    }
+    if let Some(timeout) = options.read_timeout() {
+        builder = builder.read_timeout(timeout);
+    }
+
+    builder = match options.http_version {
+        HttpVersion::Auto => builder,
+        HttpVersion::Http1 => builder.http1_only(),
+        HttpVersion::Http2 => builder.http2_prior_knowledge(),
+        #[cfg(feature = "http3")]
+        HttpVersion::Http3 => builder.http3_prior_knowledge(),
+        #[cfg(not(feature = "http3"))]
+        HttpVersion::Http3 => {
+            return Err(FetchError::new(
+                FetchErrorKind::ClientBuild,
+                "This build has no HTTP/3 support; it needs the `http3` feature",
+            ));
+        }
+    };
+
+    builder
+        .build()
+        .map_err(|e| FetchError::with_source(FetchErrorKind::ClientBuild, &e))
+}
+
+/// Sends every connection through the Unix socket at `path`. Proxies and
+/// DNS are bypassed.
+#[cfg(unix)]
+fn unix_socket(builder: ClientBuilder, path: &str) -> Result<ClientBuilder, FetchError> {
+    Ok(builder.unix_socket(path))
+}
+
+#[cfg(not(unix))]
+fn unix_socket(_builder: ClientBuilder, _path: &str) -> Result<ClientBuilder, FetchError> {
+    Err(FetchError::new(
+        FetchErrorKind::InvalidSocket,
+        "Unix sockets are not supported on this platform",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::certificates::NewCertificate;
+    use crate::connection::ConnectionCache;
+    use crate::redirect;
+    use crate::test_support::{self, temp_file, CA_KEY_PEM, CA_PEM};
+
+    fn clients(certificates: CertificateStore, options: &RequestOptions) -> RequestClients {
+        RequestClients::new(
+            Arc::new(ClientPool::default()),
+            Arc::new(CookieJars::default()),
+            Arc::new(certificates),
+            options.clone(),
+        )
+    }
+
+    /// Options that send `other.test` to the local listeners.
+    fn options() -> RequestOptions {
+        RequestOptions {
+            dns_overrides: vec![DnsOverride {
+                host: "other.test".to_string(),
+                addresses: vec!["127.0.0.1".to_string()],
+            }],
+            ..RequestOptions::default()
+        }
+    }
+
+    /// Follows a redirect from 127.0.0.1 to `target_host` and returns how
+    /// many distinct clients the chain needed.
+    async fn clients_for_redirect(certificates: CertificateStore, target_host: &str) -> usize {
+        let first = test_support::listen().await;
+        let second = test_support::listen().await;
+        let target = format!(
+            "http://{}:{}/landed",
+            target_host,
+            second.local_addr().unwrap().port()
+        );
+        let url = Url::parse(&format!("http://{}/start", first.local_addr().unwrap())).unwrap();
+        let redirect = test_support::response("302 Found", &[("Location", &target)], "");
+        let first = tokio::spawn(test_support::serve(first, vec![redirect]));
+        let second = tokio::spawn(test_support::serve(
+            second,
+            vec![test_support::response("200 OK", &[], "done")],
+        ));
+
+        let options = options();
+        let clients = clients(certificates, &options);
+        let request = clients.for_url(&url).unwrap().get(url).build().unwrap();
+        let (response, _, hops) = redirect::follow(
+            &clients,
+            request,
+            &options,
+            &ConnectionCache::default(),
+            &mut Vec::new(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(hops.len(), 1);
+        first.await.unwrap();
+        second.await.unwrap();
+        clients.pool.reset()
+    }
+
+    #[tokio::test]
+    async fn redirects_to_another_host_do_not_keep_the_certificates() {
+        let certificates = CertificateStore::default();
+        certificates
+            .add(NewCertificate::PemIdentity {
+                name: "local".to_string(),
+                host_pattern: "127.0.0.1".to_string(),
+                cert_path: temp_file(CA_PEM),
+                key_path: temp_file(CA_KEY_PEM),
+            })
+            .unwrap();
+        certificates
+            .add(NewCertificate::CaBundle {
+                name: "local CA".to_string(),
+                host_pattern: "127.0.0.1".to_string(),
+                path: temp_file(CA_PEM),
+            })
+            .unwrap();
+
+        // The second host matches no certificate, so it needs its own client
+        assert_eq!(clients_for_redirect(certificates, "other.test").await, 2);
+    }
+
+    #[tokio::test]
+    async fn redirects_within_a_certificate_scope_share_a_client() {
+        let certificates = CertificateStore::default();
+        certificates
+            .add(NewCertificate::CaBundle {
+                name: "everywhere".to_string(),
+                host_pattern: "*".to_string(),
+                path: temp_file(CA_PEM),
+            })
+            .unwrap();
+        assert_eq!(clients_for_redirect(certificates, "other.test").await, 1);
+    }
+
+    /// Sends a GET with `version` forced and returns the protocol used and
+    /// the body.
+    async fn get(version: HttpVersion, url: &str) -> (reqwest::Version, String) {
+        let options = RequestOptions {
+            http_version: version,
+            accept_invalid_certs: true,
+            proxy: ProxySettings::None,
+            ..RequestOptions::default()
+        };
+        let clients = clients(CertificateStore::default(), &options);
+        let url = Url::parse(url).unwrap();
+        let mut request = clients.for_url(&url).unwrap().get(url);
+        if version == HttpVersion::Http3 {
+            request = request.version(reqwest::Version::HTTP_3);
+        }
+        let response = request.send().await.unwrap();
+        (response.version(), response.text().await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn forced_http1_is_used() {
+        let listener = test_support::listen().await;
+        let url = format!("http://{}/h1", listener.local_addr().unwrap());
+        let server = tokio::spawn(test_support::serve(
+            listener,
+            vec![test_support::response("200 OK", &[], "one")],
+        ));
+
+        let (version, body) = get(HttpVersion::Http1, &url).await;
+        assert_eq!(version, reqwest::Version::HTTP_11);
+        assert_eq!(body, "one");
+        assert!(server.await.unwrap()[0].starts_with("GET /h1 HTTP/1.1\r\n"));
+    }
+
+    #[tokio::test]
+    async fn forced_http2_uses_h2c_with_prior_knowledge() {
+        let listener = test_support::listen().await;
+        let url = format!("http://{}/h2c", listener.local_addr().unwrap());
+        let server = tokio::spawn(test_support::serve_h2c(listener, "two"));
+
+        let (version, body) = get(HttpVersion::Http2, &url).await;
+        assert_eq!(version, reqwest::Version::HTTP_2);
+        assert_eq!(body, "two");
+        assert_eq!(server.await.unwrap(), "/h2c");
+    }
+
+    #[cfg(feature = "http3")]
+    #[tokio::test]
+    async fn forced_http3_goes_over_quic() {
+        let endpoint = test_support::h3_endpoint();
+        let url = format!("https://{}/h3", endpoint.local_addr().unwrap());
+        let server = tokio::spawn(test_support::serve_h3(endpoint, "three"));
+
+        let (version, body) = get(HttpVersion::Http3, &url).await;
+        assert_eq!(version, reqwest::Version::HTTP_3);
+        assert_eq!(body, "three");
+        assert_eq!(server.await.unwrap(), "/h3");
+    }
+
+    /// Sends a GET to the local TLS server and returns the body, along with
+    /// what the server saw.
+    async fn get_tls(
+        certificates: CertificateStore,
+        accept_invalid_certs: bool,
+    ) -> (Result<String, FetchError>, Option<String>) {
+        let listener = test_support::listen().await;
+        let url = Url::parse(&format!("https://{}/tls", listener.local_addr().unwrap())).unwrap();
+        let server = tokio::spawn(test_support::serve_tls(
+            listener,
+            test_support::response("200 OK", &[], "secure"),
+        ));
+
+        let options = RequestOptions {
+            accept_invalid_certs,
+            proxy: ProxySettings::None,
+            ..RequestOptions::default()
+        };
+        let clients = clients(certificates, &options);
+        let result = async {
+            let response = clients.for_url(&url)?.get(url).send().await?;
+            Ok(response.text().await?)
+        }
+        .await;
+        (result, server.await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn a_registered_ca_verifies_the_server() {
+        let certificates = CertificateStore::default();
+        certificates
+            .add(NewCertificate::CaBundle {
+                name: "local CA".to_string(),
+                host_pattern: "127.0.0.1".to_string(),
+                path: temp_file(CA_PEM),
+            })
+            .unwrap();
+
+        let (body, head) = get_tls(certificates, false).await;
+        assert_eq!(body.unwrap(), "secure");
+        assert!(head.unwrap().starts_with("GET /tls HTTP/1.1\r\n"));
+    }
+
+    #[tokio::test]
+    async fn an_unknown_ca_explains_how_to_trust_it() {
+        let (body, head) = get_tls(CertificateStore::default(), false).await;
+        let error = body.unwrap_err();
+        assert_eq!(error.kind, FetchErrorKind::Tls);
+        assert!(
+            error
+                .message
+                .starts_with("Server certificate verification failed: "),
+            "{}",
+            error.message
+        );
+        assert!(error.message.ends_with(
+            "Register the issuing CA for this host, or enable insecure mode to skip verification"
+        ));
+        assert_eq!(head, None);
+    }
+
+    #[tokio::test]
+    async fn insecure_mode_skips_verification() {
+        let (body, head) = get_tls(CertificateStore::default(), true).await;
+        assert_eq!(body.unwrap(), "secure");
+        assert!(head.is_some());
+    }
+
+    #[test]
+    fn equivalent_dns_settings_share_a_client() {
+        let pool = ClientPool::default();
+        let cookies = Arc::new(CookieJars::default());
+        let spelled = |host: &str, address: &str, server: &str| RequestOptions {
+            dns_overrides: vec![DnsOverride {
+                host: host.to_string(),
+                addresses: vec![address.to_string()],
+            }],
+            dns_server: Some(server.to_string()),
+            ..RequestOptions::default()
+        };
+        for options in [
+            spelled("other.test", "::1", "192.0.2.53"),
+            spelled(" Other.TEST ", " ::1", "192.0.2.53 "),
+        ] {
+            pool.client_for(&options, &cookies, &[]).unwrap();
+        }
+        assert_eq!(pool.reset(), 1);
+    }
+
+    #[test]
+    fn blank_unix_sockets_share_the_tcp_client() {
+        let pool = ClientPool::default();
+        let cookies = Arc::new(CookieJars::default());
+        for unix_socket in [None, Some(String::new()), Some(" ".to_string())] {
+            let options = RequestOptions {
+                unix_socket,
+                ..RequestOptions::default()
+            };
+            pool.client_for(&options, &cookies, &[]).unwrap();
+        }
+        assert_eq!(pool.reset(), 1);
+    }
+
+    #[test]
+    fn selection_follows_the_url() {
+        let certificates = CertificateStore::default();
+        certificates
+            .add(NewCertificate::CaBundle {
+                name: "corp".to_string(),
+                host_pattern: "*.internal.corp".to_string(),
+                path: temp_file(CA_PEM),
+            })
+            .unwrap();
+        let options = RequestOptions {
+            fresh_connection: true,
+            ..RequestOptions::default()
+        };
+        let clients = clients(certificates, &options);
+        let url = |url: &str| Url::parse(url).unwrap();
+        clients.for_url(&url("https://a.internal.corp/")).unwrap();
+        let corp = clients.current.lock().unwrap().as_ref().unwrap().0.clone();
+        assert_eq!(corp.len(), 1);
+        clients.for_url(&url("https://example.com/")).unwrap();
+        assert!(clients
+            .current
+            .lock()
+            .unwrap()
+            .as_ref()
+            .unwrap()
+            .0
+            .is_empty());
+    }
+}
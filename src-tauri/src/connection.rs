@@ -0,0 +1,390 @@
+use crate::dns::DnsLookup;
+use crate::options::RequestOptions;
+use crate::timing::ConnectionMarks;
+use rustls::pki_types::CertificateDer;
+use rustls::ProtocolVersion;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::VecDeque;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Mutex, MutexGuard};
+use x509_parser::extensions::GeneralName;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+/// How the response reached us: protocol, peer address or Unix socket and,
+/// for HTTPS, the negotiated TLS parameters. HTTP/3 only reports the protocol, as its QUIC
+/// handshake runs outside the hooks that observe TCP and TLS connections.
+#[derive(Serialize)]
+pub struct ConnectionInfo {
+    /// `HTTP/1.1`, `HTTP/2.0` and so on.
+    http_version: String,
+    /// The address the connection went to; the proxy's when one was used.
+    remote_ip: Option<String>,
+    remote_port: Option<u16>,
+    /// The socket path when the request went over a Unix socket, which has
+    /// no remote address.
+    unix_socket: Option<String>,
+    /// Whether a pooled connection was reused instead of a new one opened;
+    /// unknown for HTTP/3.
+    reused: Option<bool>,
+    /// How the host name was resolved for a new connection. Absent for
+    /// reused connections and IP address URLs; through a proxy, it is the
+    /// proxy's name.
+    dns: Option<DnsLookup>,
+    tls: Option<TlsInfo>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct TlsInfo {
+    /// `TLSv1_3` or `TLSv1_2`.
+    version: Option<String>,
+    /// IANA name of the suite, such as `TLS13_AES_128_GCM_SHA256`.
+    cipher_suite: Option<String>,
+    /// `h2` when the server chose HTTP/2 through ALPN and `h3` for HTTP/3.
+    /// Absent otherwise: the TLS stack does not tell a server that selected
+    /// `http/1.1` apart from one that ignored ALPN.
+    alpn_protocol: Option<String>,
+    /// Leaf first, as sent by the server.
+    peer_certificates: Vec<PeerCertificate>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct PeerCertificate {
+    subject: String,
+    issuer: String,
+    /// Entries such as `DNS:example.com` and `IP:192.0.2.1`.
+    subject_alt_names: Vec<String>,
+    serial_number: String,
+    /// Validity period as Unix seconds.
+    not_before: i64,
+    not_after: i64,
+    /// SHA-256 of the DER encoding, as colon-separated hex.
+    sha256_fingerprint: String,
+}
+
+/// How many handshakes the cache remembers before the least recently used
+/// is dropped.
+const CACHED_HANDSHAKES: usize = 256;
+
+type HandshakeKey = (String, Option<SocketAddr>);
+
+/// TLS details of the connections opened so far, so a response on a reused
+/// or resumed connection can still describe the full handshake. Bounded to
+/// the most recently used entries.
+#[derive(Default)]
+pub struct ConnectionCache {
+    handshakes: Mutex<VecDeque<(HandshakeKey, TlsInfo)>>,
+}
+
+impl ConnectionCache {
+    /// Forgets every recorded handshake, for when the pooled connections
+    /// themselves are dropped.
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    fn lock(&self) -> MutexGuard<'_, VecDeque<(HandshakeKey, TlsInfo)>> {
+        self.handshakes
+            .lock()
+            .expect("connection cache lock poisoned")
+    }
+}
+
+/// Looks up a handshake and marks it as the most recently used.
+fn cached(
+    handshakes: &mut VecDeque<(HandshakeKey, TlsInfo)>,
+    key: &HandshakeKey,
+) -> Option<TlsInfo> {
+    let index = handshakes.iter().position(|(cached, _)| cached == key)?;
+    let entry = handshakes.remove(index)?;
+    let info = entry.1.clone();
+    handshakes.push_back(entry);
+    Some(info)
+}
+
+/// Records a handshake, dropping the least recently used one when full.
+fn remember(handshakes: &mut VecDeque<(HandshakeKey, TlsInfo)>, key: HandshakeKey, info: TlsInfo) {
+    handshakes.retain(|(cached, _)| *cached != key);
+    if handshakes.len() >= CACHED_HANDSHAKES {
+        handshakes.pop_front();
+    }
+    handshakes.push_back((key, info));
+}
+
+/// Describes the connection behind `response` from what the hooks recorded
+/// while it was sent, falling back to the cache for reused connections.
+pub fn describe(
+    response: &reqwest::Response,
+    marks: &ConnectionMarks,
+    options: &RequestOptions,
+    cache: &ConnectionCache,
+) -> ConnectionInfo {
+    let remote_addr = response.remote_addr();
+    let mut tls = tls_info(response, marks, remote_addr, cache);
+    if let Some(tls) = &mut tls {
+        // QUIC always negotiates ALPN, and HTTP/3 only runs over `h3`
+        if response.version() == reqwest::Version::HTTP_3 {
+            tls.alpn_protocol = Some("h3".to_string());
+        }
+    }
+
+    ConnectionInfo {
+        http_version: format!("{:?}", response.version()),
+        remote_ip: remote_addr.map(|addr| addr.ip().to_string()),
+        remote_port: remote_addr.map(|addr| addr.port()),
+        // The path was checked before the request could be sent
+        unix_socket: options
+            .unix_socket_path()
+            .unwrap_or_default()
+            .map(str::to_string),
+        reused: (response.version() != reqwest::Version::HTTP_3)
+            .then_some(marks.connect_end.is_none()),
+        dns: marks.dns.clone(),
+        tls,
+    }
+}
+
+fn tls_info(
+    response: &reqwest::Response,
+    marks: &ConnectionMarks,
+    remote_addr: Option<SocketAddr>,
+    cache: &ConnectionCache,
+) -> Option<TlsInfo> {
+    let url = response.url();
+    if url.scheme() != "https" {
+        return None;
+    }
+    let host = url.host_str()?.trim_matches(['[', ']']);
+    let authority = format!("{}:{}", host, url.port_or_known_default()?);
+    let key = (authority, remote_addr);
+    let handshake = &marks.handshake;
+    let mut handshakes = cache.lock();
+
+    // Without a handshake with this host the connection was reused
+    if handshake.server_name.as_deref() != Some(host) || handshake.version.is_none() {
+        return cached(&mut handshakes, &key);
+    }
+
+    let mut peer_certificates: Vec<PeerCertificate> = handshake
+        .peer_chain
+        .iter()
+        .filter_map(peer_certificate)
+        .collect();
+    // Resumed sessions skip the certificate exchange; reuse what the
+    // original handshake with this host saw
+    if peer_certificates.is_empty() {
+        peer_certificates = handshakes
+            .iter()
+            .find(|((authority, _), _)| *authority == key.0)
+            .map(|(_, info)| info.peer_certificates.clone())
+            .unwrap_or_default();
+    }
+
+    let info = TlsInfo {
+        version: handshake.version.map(version_name),
+        cipher_suite: handshake.cipher_suite.map(|suite| {
+            suite
+                .as_str()
+                .map_or_else(|| format!("{:?}", suite), str::to_string)
+        }),
+        alpn_protocol: marks.negotiated_h2.then(|| "h2".to_string()),
+        peer_certificates,
+    };
+    remember(&mut handshakes, key, info.clone());
+    Some(info)
+}
+
+fn version_name(version: ProtocolVersion) -> String {
+    version
+        .as_str()
+        .map_or_else(|| format!("{:?}", version), str::to_string)
+}
+
+/// Summarizes a certificate, or `None` if it cannot be parsed, which only
+/// happens when verification is disabled.
+fn peer_certificate(der: &CertificateDer<'_>) -> Option<PeerCertificate> {
+    let (_, cert) = X509Certificate::from_der(der).ok()?;
+    let subject_alt_names = match cert.subject_alternative_name() {
+        Ok(Some(extension)) => extension
+            .value
+            .general_names
+            .iter()
+            .map(general_name)
+            .collect(),
+        _ => Vec::new(),
+    };
+    let fingerprint = Sha256::digest(der)
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<Vec<_>>()
+        .join(":");
+
+    Some(PeerCertificate {
+        subject: cert.subject().to_string(),
+        issuer: cert.issuer().to_string(),
+        subject_alt_names,
+        serial_number: cert.raw_serial_as_string().to_ascii_uppercase(),
+        not_before: cert.validity().not_before.timestamp(),
+        not_after: cert.validity().not_after.timestamp(),
+        sha256_fingerprint: fingerprint,
+    })
+}
+
+/// Formats a SAN entry the way OpenSSL prints it.
+fn general_name(name: &GeneralName<'_>) -> String {
+    match name {
+        GeneralName::DNSName(name) => format!("DNS:{}", name),
+        GeneralName::RFC822Name(email) => format!("email:{}", email),
+        GeneralName::URI(uri) => format!("URI:{}", uri),
+        GeneralName::IPAddress(bytes) => {
+            let ip = match bytes.len() {
+                4 => <[u8; 4]>::try_from(*bytes).ok().map(IpAddr::from),
+                16 => <[u8; 16]>::try_from(*bytes).ok().map(IpAddr::from),
+                _ => None,
+            };
+            match ip {
+                Some(ip) => format!("IP:{}", ip),
+                None => format!("IP:{:02X?}", bytes),
+            }
+        }
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(version: &str) -> TlsInfo {
+        TlsInfo {
+            version: Some(version.to_string()),
+            cipher_suite: None,
+            alpn_protocol: None,
+            peer_certificates: Vec::new(),
+        }
+    }
+
+    fn key(port: u16) -> HandshakeKey {
+        (format!("example.com:{}", port), None)
+    }
+
+    #[test]
+    fn cache_keeps_the_most_recently_used_handshakes() {
+        let mut handshakes = VecDeque::new();
+        for port in 0..CACHED_HANDSHAKES as u16 {
+            remember(&mut handshakes, key(port), info("TLSv1_3"));
+        }
+        // Touching the oldest entry saves it from the next eviction
+        assert!(cached(&mut handshakes, &key(0)).is_some());
+        remember(&mut handshakes, key(9999), info("TLSv1_3"));
+
+        assert_eq!(handshakes.len(), CACHED_HANDSHAKES);
+        assert!(cached(&mut handshakes, &key(0)).is_some());
+        assert!(cached(&mut handshakes, &key(1)).is_none());
+        assert!(cached(&mut handshakes, &key(9999)).is_some());
+    }
+
+    #[tokio::test]
+    async fn describes_the_handshake_of_a_real_connection() {
+        use crate::test_support;
+
+        let listener = test_support::listen().await;
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(test_support::serve_tls(
+            listener,
+            test_support::response("200 OK", &[], "secure"),
+        ));
+        let options = RequestOptions {
+            accept_invalid_certs: true,
+            proxy: crate::proxy::ProxySettings::None,
+            ..RequestOptions::default()
+        };
+        let clients = test_support::clients(&options);
+        let url = url::Url::parse(&format!("https://127.0.0.1:{}/", port)).unwrap();
+        let request = clients.for_url(&url).unwrap().get(url).build().unwrap();
+        let cache = ConnectionCache::default();
+        let (response, exchange, _) =
+            crate::redirect::follow(&clients, request, &options, &cache, &mut Vec::new())
+                .await
+                .unwrap();
+        let info = describe(&response, &exchange.marks, &options, &cache);
+        server.await.unwrap().unwrap();
+
+        assert_eq!(info.http_version, "HTTP/1.1");
+        assert_eq!(info.remote_ip.as_deref(), Some("127.0.0.1"));
+        assert_eq!(info.remote_port, Some(port));
+        assert_eq!(info.reused, Some(false));
+        let tls = info.tls.unwrap();
+        assert_eq!(tls.version.as_deref(), Some("TLSv1_3"));
+        assert!(tls.cipher_suite.unwrap().starts_with("TLS13_"));
+        // The server picked `http/1.1`, which is not reported
+        assert_eq!(tls.alpn_protocol, None);
+
+        let [leaf, ca] = &tls.peer_certificates[..] else {
+            panic!("expected the leaf and the CA");
+        };
+        assert_eq!(leaf.subject, "CN=localhost");
+        assert_eq!(leaf.issuer, "CN=Test CA");
+        assert_eq!(leaf.subject_alt_names, ["DNS:localhost", "IP:127.0.0.1"]);
+        assert!(leaf.not_before < leaf.not_after);
+        assert_eq!(ca.subject, "CN=Test CA");
+        let fingerprint: Vec<&str> = leaf.sha256_fingerprint.split(':').collect();
+        assert_eq!(fingerprint.len(), 32);
+        assert!(fingerprint
+            .iter()
+            .all(|byte| byte.len() == 2 && byte.chars().all(|c| c.is_ascii_hexdigit())));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn requests_go_through_the_unix_socket() {
+        use crate::test_support;
+
+        let path = std::env::temp_dir().join(format!("socket-{:016x}", fastrand::u64(..)));
+        let listener = tokio::net::UnixListener::bind(&path).unwrap();
+        let server = tokio::spawn(test_support::serve_unix(
+            listener,
+            test_support::response("200 OK", &[], "over the socket"),
+        ));
+        let options = RequestOptions {
+            unix_socket: Some(format!("unix://{}", path.display())),
+            ..RequestOptions::default()
+        };
+        let clients = test_support::clients(&options);
+        let url = url::Url::parse("http://api.test:8080/v1/items?page=2").unwrap();
+        let request = clients.for_url(&url).unwrap().get(url).build().unwrap();
+        let cache = ConnectionCache::default();
+        let (response, exchange, _) =
+            crate::redirect::follow(&clients, request, &options, &cache, &mut Vec::new())
+                .await
+                .unwrap();
+        let info = describe(&response, &exchange.marks, &options, &cache);
+        assert_eq!(response.text().await.unwrap(), "over the socket");
+        let _ = std::fs::remove_file(&path);
+
+        // The URL still supplies the path and Host
+        let head = server.await.unwrap();
+        assert!(
+            head.starts_with("GET /v1/items?page=2 HTTP/1.1\r\n"),
+            "{}",
+            head
+        );
+        assert!(head
+            .to_ascii_lowercase()
+            .contains("\r\nhost: api.test:8080\r\n"));
+        assert_eq!(info.unix_socket.as_deref(), Some(path.to_str().unwrap()));
+        assert_eq!(info.remote_ip, None);
+        assert!(info.tls.is_none());
+    }
+
+    #[test]
+    fn cache_replaces_a_repeated_handshake() {
+        let mut handshakes = VecDeque::new();
+        remember(&mut handshakes, key(443), info("TLSv1_2"));
+        remember(&mut handshakes, key(443), info("TLSv1_3"));
+
+        assert_eq!(handshakes.len(), 1);
+        let info = cached(&mut handshakes, &key(443)).unwrap();
+        assert_eq!(info.version.as_deref(), Some("TLSv1_3"));
+    }
+}
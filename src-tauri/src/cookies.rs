@@ -0,0 +1,426 @@
+use crate::error::{FetchError, FetchErrorKind};
+use crate::storage;
+use cookie_store::{CookieDomain, CookieExpiration, CookieStore, RawCookie};
+use reqwest::header::HeaderValue;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use time::OffsetDateTime;
+use url::Url;
+
+/// Workspace used when a request does not name one.
+pub const DEFAULT_WORKSPACE: &str = "default";
+
+/// One cookie jar per workspace, shared by every client that uses it.
+/// Changes, whether edits or cookies set by responses, are only written to
+/// disk by `write_changes`.
+#[derive(Default)]
+pub struct CookieJars {
+    file: Option<PathBuf>,
+    jars: Mutex<HashMap<String, CookieStore>>,
+    /// Whether the jars changed since they were last written.
+    dirty: AtomicBool,
+    /// Held while writing, as every write goes through the same temporary
+    /// file.
+    writing: Mutex<()>,
+}
+
+/// A cookie as listed and edited in the cookie manager.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CookieInfo {
+    pub name: String,
+    pub value: String,
+    /// The host for host-only cookies, otherwise the `Domain` attribute.
+    pub domain: String,
+    /// Whether the cookie is sent to this exact host only, not subdomains.
+    #[serde(default)]
+    pub host_only: bool,
+    #[serde(default = "root_path")]
+    pub path: String,
+    /// Expiry as Unix seconds; `None` for session cookies.
+    pub expires: Option<i64>,
+    #[serde(default)]
+    pub secure: bool,
+    #[serde(default)]
+    pub http_only: bool,
+}
+
+fn root_path() -> String {
+    "/".to_string()
+}
+
+impl CookieJars {
+    /// Loads the jars saved in `file`. A missing or unreadable file starts
+    /// with empty jars; expired cookies are dropped.
+    pub fn load(file: PathBuf) -> Self {
+        let saved: HashMap<String, Vec<cookie_store::Cookie<'static>>> =
+            storage::read_json(&file).unwrap_or_default();
+        let jars = saved
+            .into_iter()
+            .map(|(workspace, cookies)| {
+                let Ok(store) = CookieStore::from_cookies(
+                    cookies.into_iter().map(Ok::<_, std::convert::Infallible>),
+                    false,
+                );
+                (workspace, store)
+            })
+            .collect();
+        CookieJars {
+            file: Some(file),
+            jars: Mutex::new(jars),
+            ..CookieJars::default()
+        }
+    }
+
+    /// The cookie provider for one workspace's jar.
+    pub fn jar(self: &Arc<Self>, workspace: &str) -> WorkspaceJar {
+        WorkspaceJar {
+            jars: Arc::clone(self),
+            workspace: workspace.to_string(),
+        }
+    }
+
+    /// Unexpired cookies in the workspace, optionally limited to those
+    /// belonging to `domain` or its subdomains.
+    pub fn list(&self, workspace: &str, domain: Option<&str>) -> Vec<CookieInfo> {
+        let jars = self.lock();
+        let Some(store) = jars.get(workspace) else {
+            return Vec::new();
+        };
+        store
+            .iter_unexpired()
+            .filter(|cookie| domain.is_none_or(|domain| in_domain(cookie, domain)))
+            .map(cookie_info)
+            .collect()
+    }
+
+    /// Adds a cookie, replacing any with the same domain, path and name.
+    pub fn set(&self, workspace: &str, cookie: CookieInfo) -> Result<(), FetchError> {
+        let invalid = |message: String| FetchError::new(FetchErrorKind::InvalidCookie, message);
+        if !cookie.path.starts_with('/') {
+            return Err(invalid(format!(
+                "Invalid cookie path {:?}: it must start with /",
+                cookie.path
+            )));
+        }
+        let domain = cookie.domain.trim().trim_start_matches('.');
+        let url = Url::parse(&format!("https://{}/", domain))
+            .map_err(|e| invalid(format!("Invalid cookie domain {:?}: {}", cookie.domain, e)))?;
+        if url.host_str() != Some(domain.to_ascii_lowercase().as_str()) {
+            return Err(invalid(format!(
+                "Invalid cookie domain {:?}: expected a host name",
+                cookie.domain
+            )));
+        }
+
+        let mut raw = RawCookie::build((cookie.name, cookie.value))
+            .path(cookie.path)
+            .secure(cookie.secure)
+            .http_only(cookie.http_only);
+        if !cookie.host_only {
+            raw = raw.domain(domain.to_string());
+        }
+        if let Some(expires) = cookie.expires {
+            let expires = OffsetDateTime::from_unix_timestamp(expires)
+                .map_err(|e| invalid(format!("Invalid cookie expiry {}: {}", expires, e)))?;
+            raw = raw.expires(expires);
+        }
+
+        self.lock()
+            .entry(workspace.to_string())
+            .or_default()
+            .insert_raw(&raw.build(), &url)
+            .map_err(|e| invalid(format!("Cookie rejected: {}", e)))?;
+        self.changed();
+        Ok(())
+    }
+
+    /// Removes one cookie. Returns false when it did not exist.
+    pub fn delete(&self, workspace: &str, domain: &str, path: &str, name: &str) -> bool {
+        let removed = self
+            .lock()
+            .get_mut(workspace)
+            .and_then(|store| store.remove(domain, path, name))
+            .is_some();
+        if removed {
+            self.changed();
+        }
+        removed
+    }
+
+    /// Removes every cookie in the workspace, or only those belonging to
+    /// `domain` and its subdomains. Returns how many were removed.
+    pub fn clear(&self, workspace: &str, domain: Option<&str>) -> usize {
+        let removed = {
+            let mut jars = self.lock();
+            let Some(store) = jars.get_mut(workspace) else {
+                return 0;
+            };
+            let doomed: Vec<(String, String, String)> = store
+                .iter_any()
+                .filter(|cookie| domain.is_none_or(|domain| in_domain(cookie, domain)))
+                .map(|cookie| {
+                    (
+                        String::from(&cookie.domain),
+                        String::from(&cookie.path),
+                        cookie.name().to_string(),
+                    )
+                })
+                .collect();
+            for (domain, path, name) in &doomed {
+                store.remove(domain, path, name);
+            }
+            doomed.len()
+        };
+        if removed > 0 {
+            self.changed();
+        }
+        removed
+    }
+
+    /// Writes the jars out if they changed, on a blocking thread. Called
+    /// after each edit and once a request is done, so a redirect chain
+    /// setting cookies on every hop is written once.
+    pub async fn write_changes(self: &Arc<Self>) -> Result<(), FetchError> {
+        let jars = Arc::clone(self);
+        tokio::task::spawn_blocking(move || jars.flush())
+            .await
+            .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, CookieStore>> {
+        self.jars.lock().expect("cookie jar lock poisoned")
+    }
+
+    fn changed(&self) {
+        self.dirty.store(true, Ordering::Release);
+    }
+
+    /// Writes all unexpired cookies, session cookies included, so a login
+    /// survives restarting the app. Does nothing when nothing changed since
+    /// the last write; a failed write is tried again next time.
+    fn flush(&self) -> Result<(), FetchError> {
+        let Some(file) = &self.file else {
+            return Ok(());
+        };
+        let _writing = self.writing.lock().expect("cookie file lock poisoned");
+        if !self.dirty.swap(false, Ordering::AcqRel) {
+            return Ok(());
+        }
+        // Copy the cookies out so the jars are not locked during the write
+        let saved: HashMap<String, Vec<cookie_store::Cookie<'static>>> = self
+            .lock()
+            .iter()
+            .map(|(workspace, store)| {
+                let cookies = store.iter_unexpired().cloned().collect();
+                (workspace.clone(), cookies)
+            })
+            .collect();
+        storage::write_json(file, &saved).inspect_err(|_| self.changed())
+    }
+}
+
+/// A workspace's jar as seen by reqwest, which consults it on every request
+/// and redirect hop.
+pub struct WorkspaceJar {
+    jars: Arc<CookieJars>,
+    workspace: String,
+}
+
+impl reqwest::cookie::CookieStore for WorkspaceJar {
+    fn set_cookies(&self, cookie_headers: &mut dyn Iterator<Item = &HeaderValue>, url: &Url) {
+        let cookies = cookie_headers
+            .filter_map(|value| value.to_str().ok())
+            .filter_map(|value| RawCookie::parse(value.to_string()).ok());
+        self.jars
+            .lock()
+            .entry(self.workspace.clone())
+            .or_default()
+            .store_response_cookies(cookies, url);
+        self.jars.changed();
+    }
+
+    fn cookies(&self, url: &Url) -> Option<HeaderValue> {
+        let jars = self.jars.lock();
+        let header = jars
+            .get(&self.workspace)?
+            .get_request_values(url)
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<_>>()
+            .join("; ");
+        if header.is_empty() {
+            return None;
+        }
+        HeaderValue::from_str(&header).ok()
+    }
+}
+
+fn in_domain(cookie: &cookie_store::Cookie<'_>, domain: &str) -> bool {
+    let domain = domain.trim_start_matches('.').to_ascii_lowercase();
+    let cookie_domain = String::from(&cookie.domain);
+    cookie_domain == domain || cookie_domain.ends_with(&format!(".{}", domain))
+}
+
+fn cookie_info(cookie: &cookie_store::Cookie<'_>) -> CookieInfo {
+    CookieInfo {
+        name: cookie.name().to_string(),
+        value: cookie.value().to_string(),
+        domain: String::from(&cookie.domain),
+        host_only: matches!(cookie.domain, CookieDomain::HostOnly(_)),
+        path: String::from(&cookie.path),
+        expires: match cookie.expires {
+            CookieExpiration::AtUtc(at) => Some(at.unix_timestamp()),
+            CookieExpiration::SessionEnd => None,
+        },
+        secure: cookie.secure().unwrap_or(false),
+        http_only: cookie.http_only().unwrap_or(false),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::cookie::CookieStore as _;
+
+    fn cookie(domain: &str, path: &str, host_only: bool) -> CookieInfo {
+        CookieInfo {
+            name: "session".to_string(),
+            value: "abc".to_string(),
+            domain: domain.to_string(),
+            host_only,
+            path: path.to_string(),
+            expires: None,
+            secure: false,
+            http_only: false,
+        }
+    }
+
+    fn temp_file(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("cookies-{}-{}.json", name, fastrand::u64(..)))
+    }
+
+    #[test]
+    fn in_domain_matches_the_domain_and_subdomains() {
+        let jars = CookieJars::default();
+        jars.set("w", cookie("api.example.com", "/", true)).unwrap();
+        let stored: Vec<_> = jars.lock()["w"].iter_any().cloned().collect();
+        let stored = &stored[0];
+        assert!(in_domain(stored, "api.example.com"));
+        assert!(in_domain(stored, "example.com"));
+        assert!(in_domain(stored, ".Example.COM"));
+        assert!(!in_domain(stored, "ample.com"));
+        assert!(!in_domain(stored, "other.example.com"));
+    }
+
+    #[test]
+    fn lists_by_workspace_and_domain() {
+        let jars = CookieJars::default();
+        jars.set("a", cookie("example.com", "/", false)).unwrap();
+        jars.set("a", cookie("other.org", "/docs", true)).unwrap();
+        assert_eq!(jars.list("a", None).len(), 2);
+        assert_eq!(jars.list("b", None).len(), 0);
+
+        let listed = jars.list("a", Some("www.example.com"));
+        assert!(listed.is_empty());
+        let listed = jars.list("a", Some("other.org"));
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].path, "/docs");
+        assert!(listed[0].host_only);
+    }
+
+    #[test]
+    fn rejects_bad_paths_and_domains() {
+        let jars = CookieJars::default();
+        for (domain, path) in [
+            ("example.com", "api"),
+            ("example.com", ""),
+            ("example.com/x", "/"),
+            ("user@example.com", "/"),
+            ("", "/"),
+        ] {
+            let err = jars.set("w", cookie(domain, path, true)).unwrap_err();
+            assert_eq!(
+                err.kind,
+                FetchErrorKind::InvalidCookie,
+                "{} {}",
+                domain,
+                path
+            );
+        }
+        assert!(jars.list("w", None).is_empty());
+    }
+
+    #[tokio::test]
+    async fn response_cookies_are_written_once_the_request_is_done() {
+        let file = temp_file("responses");
+        let jars = Arc::new(CookieJars::load(file.clone()));
+        let url = Url::parse("https://example.com/login").unwrap();
+        let header = HeaderValue::from_static("token=xyz; Path=/");
+        jars.jar("w").set_cookies(&mut [&header].into_iter(), &url);
+        assert!(!file.exists());
+
+        jars.write_changes().await.unwrap();
+        let reloaded = CookieJars::load(file.clone());
+        let listed = reloaded.list("w", None);
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].name, "token");
+        std::fs::remove_file(&file).unwrap();
+    }
+
+    #[tokio::test]
+    async fn failed_writes_are_reported_and_retried() {
+        // A directory in place of the file makes the rename fail
+        let file = temp_file("blocked");
+        std::fs::create_dir_all(&file).unwrap();
+        let jars = Arc::new(CookieJars::load(file.clone()));
+        jars.jar("w").set_cookies(
+            &mut [&HeaderValue::from_static("a=1")].into_iter(),
+            &Url::parse("http://example.com/").unwrap(),
+        );
+        let err = jars.write_changes().await.unwrap_err();
+        assert_eq!(err.kind, FetchErrorKind::Storage);
+
+        std::fs::remove_dir_all(&file).unwrap();
+        jars.write_changes().await.unwrap();
+        assert_eq!(CookieJars::load(file.clone()).list("w", None).len(), 1);
+        std::fs::remove_file(&file).unwrap();
+        let _ = std::fs::remove_file(file.with_extension("partial"));
+    }
+
+    #[tokio::test]
+    async fn edits_are_written_by_write_changes() {
+        let file = temp_file("edits");
+        let jars = Arc::new(CookieJars::load(file.clone()));
+        jars.set("w", cookie("example.com", "/", true)).unwrap();
+        assert!(!file.exists());
+        jars.write_changes().await.unwrap();
+        assert_eq!(CookieJars::load(file.clone()).list("w", None).len(), 1);
+
+        assert!(jars.delete("w", "example.com", "/", "session"));
+        jars.write_changes().await.unwrap();
+        assert!(CookieJars::load(file.clone()).list("w", None).is_empty());
+        std::fs::remove_file(&file).unwrap();
+    }
+
+    #[tokio::test]
+    async fn removing_nothing_writes_nothing() {
+        let file = temp_file("untouched");
+        let jars = Arc::new(CookieJars::load(file.clone()));
+        jars.set("w", cookie("example.com", "/", true)).unwrap();
+        jars.write_changes().await.unwrap();
+        std::fs::remove_file(&file).unwrap();
+
+        assert!(!jars.delete("w", "example.com", "/", "missing"));
+        assert_eq!(jars.clear("w", Some("other.org")), 0);
+        assert_eq!(jars.clear("empty", None), 0);
+        jars.write_changes().await.unwrap();
+        assert!(!file.exists());
+
+        assert_eq!(jars.clear("w", None), 1);
+        jars.write_changes().await.unwrap();
+        assert!(CookieJars::load(file.clone()).list("w", None).is_empty());
+        std::fs::remove_file(&file).unwrap();
+    }
+}
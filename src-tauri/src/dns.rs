@@ -0,0 +1,250 @@
+use crate::error::{FetchError, FetchErrorKind};
+use crate::options::RequestOptions;
+use crate::timing;
+use hickory_resolver::config::{NameServerConfigGroup, ResolverConfig};
+use hickory_resolver::name_server::TokioConnectionProvider;
+use hickory_resolver::TokioResolver;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// How a host name was turned into addresses for a new connection.
+#[derive(Serialize, Clone)]
+pub struct DnsLookup {
+    host: String,
+    source: DnsSource,
+    /// The server asked, for `server` lookups.
+    server: Option<String>,
+    /// In the order they are tried.
+    addresses: Vec<String>,
+}
+
+#[derive(Serialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum DnsSource {
+    /// Taken from the request's DNS overrides without a lookup.
+    Override,
+    System,
+    /// Asked of the configured DNS server.
+    Server,
+}
+
+/// Resolver that applies the request's host overrides and otherwise asks
+/// the system or the configured DNS server, reporting the lookup and its
+/// timing to the current request.
+pub struct Resolver {
+    overrides: HashMap<String, Vec<IpAddr>>,
+    server: Option<(SocketAddr, Arc<TokioResolver>)>,
+}
+
+impl Resolver {
+    pub fn new(options: &RequestOptions) -> Result<Self, FetchError> {
+        let mut overrides = HashMap::new();
+        for entry in options.normalized_dns_overrides() {
+            if entry.host.is_empty() {
+                return Err(invalid("A DNS override has no host name".to_string()));
+            }
+            if entry.addresses.is_empty() {
+                return Err(invalid(format!(
+                    "The DNS override for {} has no addresses",
+                    entry.host
+                )));
+            }
+            let addresses = entry
+                .addresses
+                .iter()
+                .map(|address| {
+                    address.parse::<IpAddr>().map_err(|_| {
+                        invalid(format!(
+                            "Invalid address {:?} in the DNS override for {}",
+                            address, entry.host
+                        ))
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            if overrides.contains_key(&entry.host) {
+                return Err(invalid(format!(
+                    "{} has more than one DNS override",
+                    entry.host
+                )));
+            }
+            overrides.insert(entry.host, addresses);
+        }
+
+        let server = match options.normalized_dns_server() {
+            None => None,
+            Some(server) => {
+                let address = parse_server(&server).ok_or_else(|| {
+                    invalid(format!(
+                        "Invalid DNS server {:?}: expected an IP address, optionally with a port",
+                        server
+                    ))
+                })?;
+                let config = ResolverConfig::from_parts(
+                    None,
+                    Vec::new(),
+                    NameServerConfigGroup::from_ips_clear(&[address.ip()], address.port(), true),
+                );
+                let resolver =
+                    TokioResolver::builder_with_config(config, TokioConnectionProvider::default())
+                        .build();
+                Some((address, Arc::new(resolver)))
+            }
+        };
+
+        Ok(Resolver { overrides, server })
+    }
+}
+
+impl Resolve for Resolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_string();
+        if let Some(addresses) = self.overrides.get(&host.to_ascii_lowercase()) {
+            let addrs: Vec<_> = addresses.iter().map(|&ip| SocketAddr::new(ip, 0)).collect();
+            return Box::pin(async move {
+                record(host, DnsSource::Override, None, &addrs);
+                Ok(Box::new(addrs.into_iter()) as Addrs)
+            });
+        }
+
+        let server = self.server.clone();
+        Box::pin(async move {
+            timing::record(|marks| marks.dns_start = Some(Instant::now()));
+            let (addrs, source, server): (Vec<_>, _, _) = match server {
+                Some((address, resolver)) => {
+                    let lookup = resolver.lookup_ip(host.as_str()).await?;
+                    let addrs = lookup.iter().map(|ip| SocketAddr::new(ip, 0)).collect();
+                    (addrs, DnsSource::Server, Some(address.to_string()))
+                }
+                None => {
+                    let addrs = tokio::net::lookup_host((host.as_str(), 0)).await?.collect();
+                    (addrs, DnsSource::System, None)
+                }
+            };
+            timing::record(|marks| marks.dns_end = Some(Instant::now()));
+            record(host, source, server, &addrs);
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+fn record(host: String, source: DnsSource, server: Option<String>, addrs: &[SocketAddr]) {
+    let lookup = DnsLookup {
+        host,
+        source,
+        server,
+        addresses: addrs.iter().map(|addr| addr.ip().to_string()).collect(),
+    };
+    timing::record(|marks| marks.dns = Some(lookup));
+}
+
+/// Parses `ip`, `ip:port` or `[ipv6]:port`, defaulting to port 53.
+fn parse_server(server: &str) -> Option<SocketAddr> {
+    if let Ok(address) = server.parse::<SocketAddr>() {
+        return Some(address);
+    }
+    let ip = server.trim_start_matches('[').trim_end_matches(']');
+    ip.parse::<IpAddr>().ok().map(|ip| SocketAddr::new(ip, 53))
+}
+
+fn invalid(message: String) -> FetchError {
+    FetchError::new(FetchErrorKind::InvalidDns, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::DnsOverride;
+
+    fn with_overrides(entries: &[(&str, &[&str])]) -> RequestOptions {
+        RequestOptions {
+            dns_overrides: entries
+                .iter()
+                .map(|(host, addresses)| DnsOverride {
+                    host: host.to_string(),
+                    addresses: addresses.iter().map(|a| a.to_string()).collect(),
+                })
+                .collect(),
+            ..RequestOptions::default()
+        }
+    }
+
+    fn rejected(options: &RequestOptions) -> String {
+        let err = Resolver::new(options).err().unwrap();
+        assert_eq!(err.kind, FetchErrorKind::InvalidDns);
+        err.message
+    }
+
+    #[test]
+    fn overrides_are_normalized() {
+        let options = with_overrides(&[(" API.Example.com ", &[" 127.0.0.1", "::1 ", " "])]);
+        let resolver = Resolver::new(&options).unwrap();
+        assert_eq!(
+            resolver.overrides["api.example.com"],
+            [
+                "127.0.0.1".parse::<IpAddr>().unwrap(),
+                "::1".parse().unwrap()
+            ]
+        );
+    }
+
+    #[test]
+    fn overrides_without_addresses_are_rejected() {
+        let message = rejected(&with_overrides(&[("example.com", &[])]));
+        assert_eq!(message, "The DNS override for example.com has no addresses");
+        rejected(&with_overrides(&[("example.com", &["", "  "])]));
+        rejected(&with_overrides(&[("  ", &["127.0.0.1"])]));
+    }
+
+    #[test]
+    fn duplicate_hosts_are_rejected() {
+        let options = with_overrides(&[
+            ("example.com", &["127.0.0.1"]),
+            ("Example.COM ", &["127.0.0.2"]),
+        ]);
+        assert_eq!(
+            rejected(&options),
+            "example.com has more than one DNS override"
+        );
+    }
+
+    #[test]
+    fn invalid_addresses_are_rejected() {
+        rejected(&with_overrides(&[("example.com", &["localhost"])]));
+    }
+
+    #[test]
+    fn blank_servers_mean_the_system_resolver() {
+        let options = RequestOptions {
+            dns_server: Some("  ".to_string()),
+            ..RequestOptions::default()
+        };
+        assert!(Resolver::new(&options).unwrap().server.is_none());
+        let options = RequestOptions {
+            dns_server: Some("dns.example".to_string()),
+            ..RequestOptions::default()
+        };
+        rejected(&options);
+    }
+
+    #[test]
+    fn servers_default_to_port_53() {
+        assert_eq!(
+            parse_server("192.0.2.1"),
+            Some("192.0.2.1:53".parse().unwrap())
+        );
+        assert_eq!(
+            parse_server("192.0.2.1:5353"),
+            Some("192.0.2.1:5353".parse().unwrap())
+        );
+        assert_eq!(parse_server("[::1]"), Some("[::1]:53".parse().unwrap()));
+        assert_eq!(
+            parse_server("[::1]:5353"),
+            Some("[::1]:5353".parse().unwrap())
+        );
+        assert_eq!(parse_server("dns.example"), None);
+    }
+}
@@ -0,0 +1,229 @@
+use crate::encoding::BodyReader;
+use crate::error::{FetchError, FetchErrorKind};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
+
+/// How much of a streamed body is kept in memory as a preview.
+pub const PREVIEW_BYTES: usize = 64 * 1024;
+
+/// Minimum time between two progress reports.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Progress of a body being written to disk.
+#[derive(Serialize, Clone)]
+pub struct DownloadProgress {
+    /// Bytes received so far, before decoding, so they compare with `total`.
+    pub bytes: u64,
+    /// The announced `Content-Length`, when the server sent one.
+    pub total: Option<u64>,
+    /// Average rate since the body started arriving.
+    pub bytes_per_second: f64,
+}
+
+/// A request to stream the response body into a file rather than memory.
+pub struct Download {
+    pub path: PathBuf,
+    pub on_progress: Box<dyn Fn(DownloadProgress) + Send + Sync>,
+}
+
+/// A body that was read to the end with only its start kept in memory.
+pub struct Streamed {
+    pub preview: Vec<u8>,
+    pub size: u64,
+}
+
+impl Download {
+    /// Streams the body into the file, reporting progress along the way.
+    /// `head` is the part of the body already read from `body`. Data
+    /// goes to a `.partial` file that is renamed once complete, so a failed
+    /// or cancelled download leaves nothing under the chosen name.
+    pub async fn write(self, head: &[u8], body: &mut BodyReader) -> Result<Streamed, FetchError> {
+        let total = body.content_length();
+        let mut partial = PartialFile::create(&self.path).await?;
+        partial.write(head).await?;
+        let started = Instant::now();
+        let mut last_report = started;
+        let mut preview = head[..head.len().min(PREVIEW_BYTES)].to_vec();
+        let mut size = head.len() as u64;
+        let report = |bytes: u64| {
+            let elapsed = started.elapsed().as_secs_f64();
+            (self.on_progress)(DownloadProgress {
+                bytes,
+                total,
+                bytes_per_second: if elapsed > 0.0 {
+                    bytes as f64 / elapsed
+                } else {
+                    0.0
+                },
+            });
+        };
+
+        while let Some(chunk) = body.chunk().await? {
+            partial.write(&chunk).await?;
+            let keep = PREVIEW_BYTES.saturating_sub(preview.len()).min(chunk.len());
+            preview.extend_from_slice(&chunk[..keep]);
+            size += chunk.len() as u64;
+            if last_report.elapsed() >= PROGRESS_INTERVAL {
+                report(body.received());
+                last_report = Instant::now();
+            }
+        }
+
+        partial.commit(&self.path).await?;
+        report(body.received());
+        Ok(Streamed { preview, size })
+    }
+}
+
+/// The file being written. Dropping it before `commit`, for example when
+/// the request is cancelled, deletes it.
+struct PartialFile {
+    path: PathBuf,
+    file: Option<tokio::fs::File>,
+}
+
+impl PartialFile {
+    async fn create(target: &Path) -> Result<Self, FetchError> {
+        let mut path = target.as_os_str().to_owned();
+        path.push(".partial");
+        let path = PathBuf::from(path);
+        let file = tokio::fs::File::create(&path)
+            .await
+            .map_err(|e| write_error(target, &e))?;
+        Ok(PartialFile {
+            path,
+            file: Some(file),
+        })
+    }
+
+    async fn write(&mut self, bytes: &[u8]) -> Result<(), FetchError> {
+        let file = self.file.as_mut().expect("partial file already committed");
+        file.write_all(bytes)
+            .await
+            .map_err(|e| write_error(&self.path, &e))
+    }
+
+    async fn commit(mut self, target: &Path) -> Result<(), FetchError> {
+        let mut file = self.file.take().expect("partial file already committed");
+        file.flush().await.map_err(|e| write_error(target, &e))?;
+        drop(file);
+        tokio::fs::rename(&self.path, target)
+            .await
+            .map_err(|e| write_error(target, &e))
+    }
+}
+
+impl Drop for PartialFile {
+    fn drop(&mut self) {
+        // Close first; Windows cannot delete a file that is still open
+        drop(self.file.take());
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+fn write_error(path: &Path, err: &std::io::Error) -> FetchError {
+    FetchError::with_context(
+        FetchErrorKind::Storage,
+        format_args!("Failed to write {}", path.display()),
+        err,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{body_reader, failing_body_reader};
+    use std::sync::{Arc, Mutex};
+
+    fn target() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("download-{:016x}", fastrand::u64(..)));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.join("body.bin")
+    }
+
+    fn partial(target: &Path) -> PathBuf {
+        let mut path = target.as_os_str().to_owned();
+        path.push(".partial");
+        PathBuf::from(path)
+    }
+
+    /// A download into `path` that records its progress reports.
+    fn download(path: &Path) -> (Download, Arc<Mutex<Vec<DownloadProgress>>>) {
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&reports);
+        let download = Download {
+            path: path.to_path_buf(),
+            on_progress: Box::new(move |progress| recorded.lock().unwrap().push(progress)),
+        };
+        (download, reports)
+    }
+
+    #[tokio::test]
+    async fn streams_the_body_after_the_head_into_the_target() {
+        let path = target();
+        let (download, reports) = download(&path);
+        let mut body = body_reader(["lo, ", "world"], true);
+        let streamed = download.write(b"hel", &mut body).await.unwrap();
+
+        assert_eq!(std::fs::read(&path).unwrap(), b"hello, world");
+        assert!(!partial(&path).exists());
+        assert_eq!(streamed.preview, b"hello, world");
+        assert_eq!(streamed.size, 12);
+
+        // The last report covers the whole body, counted as received
+        let reports = reports.lock().unwrap();
+        let last = reports.last().unwrap();
+        assert_eq!((last.bytes, last.total), (9, Some(9)));
+    }
+
+    #[tokio::test]
+    async fn the_preview_is_capped() {
+        let path = target();
+        let (download, reports) = download(&path);
+        let chunks = [vec![b'a'; PREVIEW_BYTES - 10], vec![b'b'; 100]];
+        let mut body = body_reader(chunks, false);
+        let streamed = download.write(&[], &mut body).await.unwrap();
+
+        assert_eq!(streamed.preview.len(), PREVIEW_BYTES);
+        assert_eq!(
+            streamed.preview[PREVIEW_BYTES - 11..PREVIEW_BYTES - 9],
+            *b"ab"
+        );
+        assert_eq!(streamed.size, PREVIEW_BYTES as u64 + 90);
+        assert_eq!(
+            std::fs::metadata(&path).unwrap().len(),
+            PREVIEW_BYTES as u64 + 90
+        );
+        let last = reports.lock().unwrap().pop().unwrap();
+        assert_eq!((last.bytes, last.total), (PREVIEW_BYTES as u64 + 90, None));
+    }
+
+    #[tokio::test]
+    async fn failed_downloads_leave_no_file() {
+        let path = target();
+        let (download, _) = download(&path);
+        let mut body = failing_body_reader(["some", "bytes"]);
+        let err = download.write(b"head", &mut body).await.err().unwrap();
+
+        assert!(err
+            .source_chain
+            .iter()
+            .any(|cause| cause == "connection reset"));
+        assert!(!path.exists());
+        assert!(!partial(&path).exists());
+    }
+
+    #[tokio::test]
+    async fn dropping_a_partial_file_deletes_it() {
+        let path = target();
+        let mut file = PartialFile::create(&path).await.unwrap();
+        file.write(b"half").await.unwrap();
+        assert!(partial(&path).exists());
+
+        drop(file);
+        assert!(!partial(&path).exists());
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,393 @@
+use crate::error::{FetchError, FetchErrorKind};
+use async_compression::tokio::bufread::{BrotliDecoder, GzipDecoder, ZlibDecoder, ZstdDecoder};
+use bytes::Bytes;
+use futures_util::TryStreamExt;
+use reqwest::header::CONTENT_ENCODING;
+use reqwest::Response;
+use serde::Serialize;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, BufReader};
+use tokio_util::io::StreamReader;
+
+/// The `Accept-Encoding` sent when the request does not set its own, so raw
+/// mode shows the same bytes that decoding would start from.
+pub const ACCEPT_ENCODING: &str = "gzip, deflate, br, zstd";
+
+/// How the body was encoded on the wire and what decoding it gave.
+#[derive(Serialize)]
+pub struct BodyEncoding {
+    /// The `Content-Encoding` header, when the server sent one.
+    content_encoding: Option<String>,
+    /// Whether the body was decoded. False in raw mode and for codings we
+    /// cannot decode, in which case the body is returned as received.
+    decoded: bool,
+    /// Body bytes as received.
+    encoded_bytes: u64,
+    /// Body bytes after decoding; the same as `encoded_bytes` when the body
+    /// was not decoded.
+    decoded_bytes: u64,
+}
+
+/// Reads a response body, undoing its content codings unless told not to.
+/// Keeps count of the bytes received so both sizes can be reported.
+pub struct BodyReader {
+    source: Source,
+    content_encoding: Option<String>,
+    content_length: Option<u64>,
+    received: Arc<AtomicU64>,
+    decoded_bytes: u64,
+}
+
+enum Source {
+    Plain(Response),
+    Decoded(Pin<Box<dyn AsyncRead + Send>>),
+}
+
+/// A content coding we can decode.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Coding {
+    Gzip,
+    Deflate,
+    Brotli,
+    Zstd,
+}
+
+impl BodyReader {
+    /// Prepares to read the body of `response`, decoding it when `decode`
+    /// is set and every coding in `Content-Encoding` is supported.
+    pub fn new(response: Response, decode: bool, warnings: &mut Vec<String>) -> Self {
+        let content_encoding = response
+            .headers()
+            .get_all(CONTENT_ENCODING)
+            .iter()
+            .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned())
+            .reduce(|all, value| format!("{}, {}", all, value));
+        let content_length = response.content_length();
+        let received = Arc::new(AtomicU64::new(0));
+
+        let codings = match (&content_encoding, decode) {
+            (Some(header), true) => match parse_codings(header) {
+                Ok(codings) => codings,
+                Err(unknown) => {
+                    warnings.push(format!(
+                        "Returned the body as received: the {:?} content coding is not supported",
+                        unknown
+                    ));
+                    Vec::new()
+                }
+            },
+            _ => Vec::new(),
+        };
+
+        let source = if codings.is_empty() {
+            Source::Plain(response)
+        } else {
+            let counter = Arc::clone(&received);
+            let stream = response
+                .bytes_stream()
+                .inspect_ok(move |chunk| {
+                    counter.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+                })
+                .map_err(io::Error::other);
+            // Codings are listed in the order they were applied, so the
+            // last one is undone first
+            let mut reader: Pin<Box<dyn AsyncRead + Send>> = Box::pin(StreamReader::new(stream));
+            for coding in codings.into_iter().rev() {
+                reader = decoder(coding, reader);
+            }
+            Source::Decoded(reader)
+        };
+
+        BodyReader {
+            source,
+            content_encoding,
+            content_length,
+            received,
+            decoded_bytes: 0,
+        }
+    }
+
+    /// The next piece of the (decoded) body, or `None` at its end.
+    pub async fn chunk(&mut self) -> Result<Option<Bytes>, FetchError> {
+        let chunk = match &mut self.source {
+            Source::Plain(response) => {
+                let chunk = response.chunk().await?;
+                if let Some(chunk) = &chunk {
+                    self.received
+                        .fetch_add(chunk.len() as u64, Ordering::Relaxed);
+                }
+                chunk
+            }
+            Source::Decoded(reader) => {
+                let mut buffer = Vec::with_capacity(64 * 1024);
+                match reader.read_buf(&mut buffer).await {
+                    Ok(0) => None,
+                    Ok(_) => Some(Bytes::from(buffer)),
+                    // An empty body, as sent with HEAD or 304, has no
+                    // compressed stream to read
+                    Err(e) if e.kind() == io::ErrorKind::UnexpectedEof && self.received() == 0 => {
+                        None
+                    }
+                    Err(e) => return Err(self.read_error(e)),
+                }
+            }
+        };
+        if let Some(chunk) = &chunk {
+            self.decoded_bytes += chunk.len() as u64;
+        }
+        Ok(chunk)
+    }
+
+    /// Body bytes received so far, before decoding.
+    pub fn received(&self) -> u64 {
+        self.received.load(Ordering::Relaxed)
+    }
+
+    /// The announced `Content-Length`, which counts encoded bytes.
+    pub fn content_length(&self) -> Option<u64> {
+        self.content_length
+    }
+
+    /// Size of the body as returned, when known before reading it.
+    pub fn decoded_length(&self) -> Option<u64> {
+        match self.source {
+            Source::Plain(_) => self.content_length,
+            Source::Decoded(_) => None,
+        }
+    }
+
+    /// Whether the body is returned still encoded.
+    pub fn is_encoded(&self) -> bool {
+        matches!(self.source, Source::Plain(_))
+            && self
+                .content_encoding
+                .as_deref()
+                .is_some_and(|header| header.split(',').any(|coding| !is_identity(coding)))
+    }
+
+    /// Summary of the encoding, once the body has been read.
+    pub fn encoding(&self) -> BodyEncoding {
+        BodyEncoding {
+            content_encoding: self.content_encoding.clone(),
+            decoded: matches!(self.source, Source::Decoded(_)),
+            encoded_bytes: self.received(),
+            decoded_bytes: self.decoded_bytes,
+        }
+    }
+
+    /// Recovers the transport error behind a failed read, or reports the
+    /// body as corrupt.
+    fn read_error(&self, err: io::Error) -> FetchError {
+        let err = match err.downcast::<reqwest::Error>() {
+            Ok(err) => return err.into(),
+            Err(err) => err,
+        };
+        FetchError::with_context(
+            FetchErrorKind::BodyDecode,
+            format_args!(
+                "Failed to decode the {} body",
+                self.content_encoding.as_deref().unwrap_or_default()
+            ),
+            &err,
+        )
+    }
+}
+
+/// The codings in a `Content-Encoding` value, or the first unsupported one.
+fn parse_codings(header: &str) -> Result<Vec<Coding>, String> {
+    header
+        .split(',')
+        .filter(|coding| !is_identity(coding))
+        .map(|coding| match coding.trim().to_ascii_lowercase().as_str() {
+            "gzip" | "x-gzip" => Ok(Coding::Gzip),
+            "deflate" => Ok(Coding::Deflate),
+            "br" => Ok(Coding::Brotli),
+            "zstd" => Ok(Coding::Zstd),
+            other => Err(other.to_string()),
+        })
+        .collect()
+}
+
+fn is_identity(coding: &str) -> bool {
+    let coding = coding.trim();
+    coding.is_empty() || coding.eq_ignore_ascii_case("identity")
+}
+
+fn decoder(
+    coding: Coding,
+    inner: Pin<Box<dyn AsyncRead + Send>>,
+) -> Pin<Box<dyn AsyncRead + Send>> {
+    let inner = BufReader::new(inner);
+    match coding {
+        Coding::Gzip => Box::pin(GzipDecoder::new(inner)),
+        // HTTP's deflate is the zlib format, not a raw deflate stream
+        Coding::Deflate => Box::pin(ZlibDecoder::new(inner)),
+        Coding::Brotli => Box::pin(BrotliDecoder::new(inner)),
+        Coding::Zstd => Box::pin(ZstdDecoder::new(inner)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_codings_in_order() {
+        assert_eq!(parse_codings("gzip"), Ok(vec![Coding::Gzip]));
+        assert_eq!(
+            parse_codings("deflate, br,zstd"),
+            Ok(vec![Coding::Deflate, Coding::Brotli, Coding::Zstd])
+        );
+    }
+
+    #[test]
+    fn coding_names_are_case_insensitive_with_aliases() {
+        assert_eq!(parse_codings(" X-GZIP "), Ok(vec![Coding::Gzip]));
+        assert_eq!(parse_codings("Br"), Ok(vec![Coding::Brotli]));
+    }
+
+    #[test]
+    fn identity_and_empty_entries_are_skipped() {
+        assert_eq!(parse_codings("identity"), Ok(vec![]));
+        assert_eq!(parse_codings(""), Ok(vec![]));
+        assert_eq!(parse_codings("gzip, , Identity"), Ok(vec![Coding::Gzip]));
+    }
+
+    #[test]
+    fn unsupported_codings_are_named() {
+        assert_eq!(parse_codings("gzip, compress"), Err("compress".to_string()));
+        assert_eq!(parse_codings("SDCH"), Err("sdch".to_string()));
+    }
+
+    /// Compresses `data` with one content coding.
+    async fn encode(coding: &str, data: &[u8]) -> Vec<u8> {
+        use async_compression::tokio::write::{BrotliEncoder, GzipEncoder, ZstdEncoder};
+        use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+        async fn finish<W: AsyncWrite + Unpin>(mut encoder: W, data: &[u8]) -> W {
+            encoder.write_all(data).await.unwrap();
+            encoder.shutdown().await.unwrap();
+            encoder
+        }
+        match coding {
+            "gzip" => finish(GzipEncoder::new(Vec::new()), data)
+                .await
+                .into_inner(),
+            "br" => finish(BrotliEncoder::new(Vec::new()), data)
+                .await
+                .into_inner(),
+            "zstd" => finish(ZstdEncoder::new(Vec::new()), data)
+                .await
+                .into_inner(),
+            other => panic!("no encoder for {}", other),
+        }
+    }
+
+    /// Fetches `body` from a local server that labels it with
+    /// `content_encoding`, and reads it back through a `BodyReader`.
+    async fn fetch(
+        content_encoding: Option<&str>,
+        body: &[u8],
+        decode: bool,
+    ) -> (Result<Vec<u8>, FetchError>, BodyEncoding, Vec<String>) {
+        use crate::options::RequestOptions;
+        use crate::test_support;
+
+        let listener = test_support::listen().await;
+        let url = url::Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        let headers: Vec<_> = content_encoding
+            .map(|coding| ("Content-Encoding", coding))
+            .into_iter()
+            .collect();
+        let server = tokio::spawn(test_support::serve(
+            listener,
+            vec![test_support::binary_response("200 OK", &headers, body)],
+        ));
+
+        let clients = test_support::clients(&RequestOptions::default());
+        let response = clients
+            .for_url(&url)
+            .unwrap()
+            .get(url)
+            .send()
+            .await
+            .unwrap();
+        let mut warnings = Vec::new();
+        let mut reader = BodyReader::new(response, decode, &mut warnings);
+        let mut read = Vec::new();
+        let result = loop {
+            match reader.chunk().await {
+                Ok(Some(chunk)) => read.extend_from_slice(&chunk),
+                Ok(None) => break Ok(read),
+                Err(err) => break Err(err),
+            }
+        };
+        server.await.unwrap();
+        (result, reader.encoding(), warnings)
+    }
+
+    const TEXT: &[u8] = b"the same sentence, over and over; the same sentence, over and over";
+
+    #[tokio::test]
+    async fn compressed_bodies_round_trip() {
+        for coding in ["gzip", "br", "zstd"] {
+            let encoded = encode(coding, TEXT).await;
+            let (body, encoding, warnings) = fetch(Some(coding), &encoded, true).await;
+
+            assert_eq!(body.unwrap(), TEXT, "{}", coding);
+            assert!(encoding.decoded);
+            assert_eq!(encoding.content_encoding.as_deref(), Some(coding));
+            assert_eq!(encoding.encoded_bytes, encoded.len() as u64);
+            assert_eq!(encoding.decoded_bytes, TEXT.len() as u64);
+            assert!(warnings.is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn stacked_codings_are_undone_in_reverse() {
+        let encoded = encode("br", &encode("gzip", TEXT).await).await;
+        let (body, encoding, _) = fetch(Some("gzip, br"), &encoded, true).await;
+        assert_eq!(body.unwrap(), TEXT);
+        assert_eq!(encoding.encoded_bytes, encoded.len() as u64);
+    }
+
+    #[tokio::test]
+    async fn raw_mode_returns_the_bytes_as_received() {
+        let encoded = encode("gzip", TEXT).await;
+        let (body, encoding, _) = fetch(Some("gzip"), &encoded, false).await;
+        assert_eq!(body.unwrap(), encoded);
+        assert!(!encoding.decoded);
+        assert_eq!(encoding.encoded_bytes, encoded.len() as u64);
+        assert_eq!(encoding.decoded_bytes, encoded.len() as u64);
+    }
+
+    #[tokio::test]
+    async fn empty_encoded_bodies_are_empty() {
+        // As sent with HEAD or 304: labelled, but without a stream to decode
+        let (body, encoding, _) = fetch(Some("gzip"), b"", true).await;
+        assert_eq!(body.unwrap(), b"");
+        assert!(encoding.decoded);
+        assert_eq!((encoding.encoded_bytes, encoding.decoded_bytes), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn corrupt_bodies_are_decode_errors() {
+        let mut encoded = encode("gzip", TEXT).await;
+        encoded.truncate(encoded.len() / 2);
+        let (body, _, _) = fetch(Some("gzip"), &encoded, true).await;
+        let err = body.unwrap_err();
+        assert_eq!(err.kind, FetchErrorKind::BodyDecode);
+        assert!(err.message.starts_with("Failed to decode the gzip body: "));
+    }
+
+    #[tokio::test]
+    async fn unsupported_codings_are_returned_as_received() {
+        let (body, encoding, warnings) = fetch(Some("compress"), b"LZW", true).await;
+        assert_eq!(body.unwrap(), b"LZW");
+        assert!(!encoding.decoded);
+        assert_eq!(warnings.len(), 1);
+    }
+}
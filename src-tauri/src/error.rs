@@ -0,0 +1,305 @@
+use crate::retry::Attempt;
+use serde::{Deserialize, Serialize};
+use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+
+/// Failure classes the frontend can react to without parsing messages.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FetchErrorKind {
+    InvalidUrl,
+    InvalidMethod,
+    InvalidHeader,
+    InvalidProxy,
+    InvalidDns,
+    InvalidSocket,
+    InvalidCertificate,
+    RequestBody,
+    ClientBuild,
+    Dns,
+    ConnectionRefused,
+    Connect,
+    Tls,
+    Timeout,
+    RedirectLoop,
+    BodyDecode,
+    Network,
+    Cancelled,
+    DuplicateRequestId,
+    InvalidCookie,
+    Storage,
+}
+
+/// Error returned by the fetch commands. Non-2xx responses are not errors;
+/// this only covers requests that could not be sent or completed.
+#[derive(Serialize, Clone, Debug)]
+pub struct FetchError {
+    pub kind: FetchErrorKind,
+    pub message: String,
+    /// Display text of each underlying cause, outermost first.
+    pub source_chain: Vec<String>,
+    /// Every attempt at the request when it failed after being sent, the
+    /// last one included.
+    pub attempts: Vec<Attempt>,
+}
+
+impl FetchError {
+    pub fn new(kind: FetchErrorKind, message: impl Into<String>) -> Self {
+        FetchError {
+            kind,
+            message: message.into(),
+            source_chain: Vec::new(),
+            attempts: Vec::new(),
+        }
+    }
+
+    /// Builds an error of the given kind from any error, keeping its causes.
+    pub fn with_source(kind: FetchErrorKind, err: &(dyn StdError + 'static)) -> Self {
+        FetchError {
+            kind,
+            message: err.to_string(),
+            source_chain: source_chain(err),
+            attempts: Vec::new(),
+        }
+    }
+
+    /// Like `with_source`, with `context` ahead of the error's own message,
+    /// as in "Failed to read cert.pem: No such file or directory".
+    pub fn with_context(
+        kind: FetchErrorKind,
+        context: impl fmt::Display,
+        err: &(dyn StdError + 'static),
+    ) -> Self {
+        FetchError {
+            message: format!("{}: {}", context, err),
+            ..FetchError::with_source(kind, err)
+        }
+    }
+}
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl From<reqwest::Error> for FetchError {
+    fn from(err: reqwest::Error) -> Self {
+        let mut error = FetchError::with_source(classify(&err), &err);
+        if let Some(tls) = causes(&err).find_map(|cause| cause.downcast_ref::<rustls::Error>()) {
+            error.message = tls_message(tls);
+        }
+        error
+    }
+}
+
+/// Explains a TLS failure in terms of what the user can change.
+fn tls_message(err: &rustls::Error) -> String {
+    use rustls::AlertDescription;
+    match err {
+        rustls::Error::InvalidCertificate(reason) => format!(
+            "Server certificate verification failed: {}. Register the issuing CA for this \
+             host, or enable insecure mode to skip verification",
+            reason
+        ),
+        rustls::Error::AlertReceived(
+            alert @ (AlertDescription::CertificateRequired
+            | AlertDescription::BadCertificate
+            | AlertDescription::UnknownCA
+            | AlertDescription::CertificateUnknown
+            | AlertDescription::HandshakeFailure),
+        ) => format!(
+            "Server rejected the TLS handshake ({:?}); it may require a client certificate \
+             for this host",
+            alert
+        ),
+        other => format!("TLS handshake failed: {}", other),
+    }
+}
+
+fn classify(err: &reqwest::Error) -> FetchErrorKind {
+    if err.is_timeout() {
+        return FetchErrorKind::Timeout;
+    }
+    if err.is_redirect() {
+        return FetchErrorKind::RedirectLoop;
+    }
+    if err.is_decode() {
+        return FetchErrorKind::BodyDecode;
+    }
+    if err.is_builder() {
+        // `http::Error` wraps the header error without listing it as a cause
+        let invalid_header = causes(err).any(|cause| match cause.downcast_ref::<http::Error>() {
+            Some(http) => {
+                http.is::<reqwest::header::InvalidHeaderName>()
+                    || http.is::<reqwest::header::InvalidHeaderValue>()
+            }
+            None => {
+                cause.is::<reqwest::header::InvalidHeaderName>()
+                    || cause.is::<reqwest::header::InvalidHeaderValue>()
+            }
+        });
+        return if invalid_header {
+            FetchErrorKind::InvalidHeader
+        } else {
+            FetchErrorKind::InvalidUrl
+        };
+    }
+    if causes(err).any(|cause| cause.is::<rustls::Error>()) {
+        return FetchErrorKind::Tls;
+    }
+    if err.is_connect() {
+        // hyper-util does not export its connect error type, only its message
+        if causes(err).any(|cause| cause.to_string() == "dns error") {
+            return FetchErrorKind::Dns;
+        }
+        let refused = causes(err).any(|cause| {
+            cause
+                .downcast_ref::<io::Error>()
+                .is_some_and(|io| io.kind() == io::ErrorKind::ConnectionRefused)
+        });
+        return if refused {
+            FetchErrorKind::ConnectionRefused
+        } else {
+            FetchErrorKind::Connect
+        };
+    }
+    FetchErrorKind::Network
+}
+
+/// Walks the cause chain, stepping into `io::Error` wrappers, whose
+/// `source()` skips the error they wrap.
+fn causes<'a>(
+    err: &'a (dyn StdError + 'static),
+) -> impl Iterator<Item = &'a (dyn StdError + 'static)> {
+    std::iter::successors(Some(err), |&current| next_cause(current))
+}
+
+fn next_cause<'a>(err: &'a (dyn StdError + 'static)) -> Option<&'a (dyn StdError + 'static)> {
+    match err.downcast_ref::<io::Error>().and_then(|io| io.get_ref()) {
+        Some(inner) => Some(inner),
+        None => err.source(),
+    }
+}
+
+/// Display text of each cause, skipping wrappers that repeat their inner
+/// error's message.
+fn source_chain(err: &(dyn StdError + 'static)) -> Vec<String> {
+    let mut chain: Vec<String> = Vec::new();
+    for cause in causes(err).skip(1) {
+        let message = cause.to_string();
+        if chain.last() != Some(&message) {
+            chain.push(message);
+        }
+    }
+    chain
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+    use std::time::Duration;
+
+    async fn send(url: &str, client: reqwest::Client) -> FetchError {
+        client.get(url).send().await.unwrap_err().into()
+    }
+
+    #[tokio::test]
+    async fn slow_servers_time_out() {
+        let listener = test_support::listen().await;
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(50))
+            .build()
+            .unwrap();
+        // Accepted, but never answered
+        let err = send(&url, client).await;
+        drop(listener);
+        assert_eq!(err.kind, FetchErrorKind::Timeout);
+    }
+
+    #[tokio::test]
+    async fn unknown_hosts_are_dns_errors() {
+        let err = send("http://missing.invalid/", reqwest::Client::new()).await;
+        assert_eq!(err.kind, FetchErrorKind::Dns);
+        assert!(!err.source_chain.is_empty());
+    }
+
+    #[tokio::test]
+    async fn closed_ports_refuse_the_connection() {
+        let listener = test_support::listen().await;
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        drop(listener);
+        let err = send(&url, reqwest::Client::new()).await;
+        assert_eq!(err.kind, FetchErrorKind::ConnectionRefused);
+    }
+
+    #[test]
+    fn builder_errors_tell_headers_from_urls() {
+        let client = reqwest::Client::new();
+        let header = client
+            .get("http://example.test/")
+            .header("bad header", "value")
+            .build()
+            .unwrap_err();
+        assert_eq!(FetchError::from(header).kind, FetchErrorKind::InvalidHeader);
+
+        let url = client.get("not a url").build().unwrap_err();
+        assert_eq!(FetchError::from(url).kind, FetchErrorKind::InvalidUrl);
+    }
+
+    #[derive(Debug)]
+    struct Wrapped(&'static str, Box<dyn StdError + Send + Sync>);
+
+    impl fmt::Display for Wrapped {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str(self.0)
+        }
+    }
+
+    impl StdError for Wrapped {
+        fn source(&self) -> Option<&(dyn StdError + 'static)> {
+            Some(&*self.1)
+        }
+    }
+
+    #[test]
+    fn source_chains_look_inside_io_errors_and_skip_repeats() {
+        let reset = io::Error::new(io::ErrorKind::ConnectionReset, "reset by peer");
+        let connect = io::Error::other(Wrapped("connect failed", Box::new(reset)));
+        let err = Wrapped("request failed", Box::new(connect));
+
+        // The io::Error shows its inner error's message, which is kept once
+        assert_eq!(source_chain(&err), ["connect failed", "reset by peer"]);
+    }
+
+    #[test]
+    fn context_goes_ahead_of_the_message() {
+        let err = io::Error::new(io::ErrorKind::NotFound, "No such file");
+        let error = FetchError::with_context(FetchErrorKind::Storage, "Failed to read a.pem", &err);
+        assert_eq!(error.kind, FetchErrorKind::Storage);
+        assert_eq!(error.message, "Failed to read a.pem: No such file");
+    }
+
+    #[test]
+    fn tls_messages_point_at_the_fix() {
+        let unknown_ca = rustls::Error::InvalidCertificate(rustls::CertificateError::UnknownIssuer);
+        let message = tls_message(&unknown_ca);
+        assert!(message.starts_with("Server certificate verification failed: "));
+        assert!(message.ends_with(
+            "Register the issuing CA for this host, or enable insecure mode to skip verification"
+        ));
+
+        let required = rustls::Error::AlertReceived(rustls::AlertDescription::CertificateRequired);
+        assert_eq!(
+            tls_message(&required),
+            "Server rejected the TLS handshake (CertificateRequired); it may require a client \
+             certificate for this host"
+        );
+
+        let other = rustls::Error::HandshakeNotComplete;
+        assert!(tls_message(&other).starts_with("TLS handshake failed: "));
+    }
+}
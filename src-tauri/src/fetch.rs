@@ -0,0 +1,465 @@
+use crate::body::ResponseBody;
+use crate::client::RequestClients;
+use crate::connection::{self, ConnectionCache, ConnectionInfo};
+use crate::download::Download;
+use crate::encoding::{self, BodyEncoding, BodyReader};
+use crate::error::{FetchError, FetchErrorKind};
+use crate::headers::{self, HeaderEntry, RequestHeader};
+use crate::options::{HttpVersion, RequestOptions};
+use crate::proxy;
+use crate::redirect::RedirectHop;
+use crate::request_body::RequestBody;
+use crate::retry::{self, Attempt};
+use crate::stored_bodies::{LimitedBody, StoredBodies};
+use crate::timing::Timings;
+use reqwest::header::{HeaderMap, HeaderValue, ACCEPT_ENCODING};
+use reqwest::{Method, Version};
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Instant;
+use url::Url;
+
+#[derive(Serialize)]
+pub struct ApiResponse {
+    status_code: u16,
+    headers: Vec<HeaderEntry>,
+    body: ResponseBody,
+    /// Content coding of the body and its size before and after decoding.
+    encoding: BodyEncoding,
+    duration_ms: u128,
+    /// Timings of the final response; each redirect carries its own.
+    timings: Timings,
+    connection: ConnectionInfo,
+    /// The ID the request could be cancelled with, generated when the
+    /// caller gave none.
+    request_id: String,
+    /// The normalized URL the request was sent to, query included.
+    request_url: String,
+    /// Where the final response came from; differs from `request_url` when
+    /// redirects were followed.
+    final_url: String,
+    /// Redirects followed on the way, in order.
+    redirects: Vec<RedirectHop>,
+    /// Every attempt made under the retry policy, the final one included.
+    attempts: Vec<Attempt>,
+    /// The file the body was written to in download mode; `body` then holds
+    /// only a preview.
+    saved_to: Option<String>,
+    /// ID under which the whole body was stored when it exceeded
+    /// `max_body_bytes`; `body` then holds only a preview.
+    stored_body: Option<String>,
+    /// Non-fatal notes about how the request was sent.
+    warnings: Vec<String>,
+}
+
+impl ApiResponse {
+    /// Adds a note found after the request completed.
+    pub fn warn(&mut self, warning: String) {
+        self.warnings.push(warning);
+    }
+}
+
+/// The request as described by the frontend.
+pub struct FetchRequest {
+    pub id: String,
+    pub url: String,
+    pub method: String,
+    pub headers: Option<Vec<RequestHeader>>,
+    pub query_params: Option<Vec<(String, String)>>,
+    pub body: Option<RequestBody>,
+}
+
+/// Sends the request with the clients for its URLs and reads the full response,
+/// or streams its body to a file when `download` is given.
+pub async fn execute(
+    clients: RequestClients,
+    request: FetchRequest,
+    options: RequestOptions,
+    connections: Arc<ConnectionCache>,
+    bodies: Arc<StoredBodies>,
+    download: Option<Download>,
+) -> Result<ApiResponse, FetchError> {
+    let FetchRequest {
+        id: request_id,
+        url,
+        method,
+        headers,
+        query_params,
+        body,
+    } = request;
+
+    // Start timing
+    let start_time = Instant::now();
+
+    // Validate method
+    let method = parse_method(&method)?;
+
+    // Validate headers before building anything
+    let header_pairs = headers::parse_request_headers(&headers.unwrap_or_default())?;
+
+    // Parse the URL and merge in the query parameters
+    let full_url = build_url(&url, &query_params.unwrap_or_default())?;
+    let request_url = full_url.to_string();
+
+    // HTTP/3 runs over QUIC, which needs TLS and cannot be proxied or sent
+    // over a Unix socket
+    let mut warnings = Vec::new();
+    let proxy = proxy::configured(&options.proxy, &full_url);
+    let unix_socket = options.unix_socket_path()?;
+    if options.http_version == HttpVersion::Http3 {
+        if full_url.scheme() != "https" {
+            return Err(FetchError::new(
+                FetchErrorKind::InvalidUrl,
+                "HTTP/3 requires an https URL",
+            ));
+        }
+        if unix_socket.is_some() {
+            return Err(FetchError::new(
+                FetchErrorKind::InvalidSocket,
+                "HTTP/3 cannot be sent over a Unix socket",
+            ));
+        }
+        if let Some(proxy) = proxy {
+            warnings.push(format!("HTTP/3 connects directly; {} was not used", proxy));
+        }
+    } else if let Some(proxy) = proxy.filter(|_| unix_socket.is_some()) {
+        warnings.push(format!("Requests over a Unix socket do not use {}", proxy));
+    }
+
+    // Build request
+    let client = clients.for_url(&full_url)?;
+    let mut request = client.request(method.clone(), full_url);
+
+    // reqwest only takes the QUIC path for requests marked as HTTP/3
+    if options.http_version == HttpVersion::Http3 {
+        request = request.version(Version::HTTP_3);
+    }
+
+    // The total timeout applies per request rather than per client
+    if let Some(timeout) = options.timeout() {
+        request = request.timeout(timeout);
+    }
+
+    // Send the body whatever the method, but flag methods where it has no
+    // defined meaning since servers and proxies may ignore or reject it
+    if let Some(body) = &body {
+        if [Method::GET, Method::HEAD, Method::DELETE, Method::OPTIONS].contains(&method) {
+            warnings.push(format!(
+                "A body was sent with {}; some servers and proxies ignore or reject it",
+                method
+            ));
+        }
+        request = body.apply(request).await?;
+    }
+
+    // Add custom headers in the order given, duplicates included. These
+    // replace same-named headers set for the body, such as Content-Type.
+    if let Some(name) = headers::first_interleaved(&header_pairs) {
+        warnings.push(format!(
+            "Repeated {} headers are sent together at its first occurrence, not in the \
+             order given",
+            name
+        ));
+    }
+    let mut custom_headers = HeaderMap::new();
+    for (name, value) in header_pairs {
+        custom_headers.append(name, value);
+    }
+    if !custom_headers.contains_key(ACCEPT_ENCODING) {
+        custom_headers.insert(
+            ACCEPT_ENCODING,
+            HeaderValue::from_static(encoding::ACCEPT_ENCODING),
+        );
+    }
+    request = request.headers(custom_headers);
+
+    // Send the request, following and recording any redirects and retrying
+    // as the policy allows
+    let request = request.build()?;
+    let (response, exchange, redirects, attempts) =
+        retry::send(&clients, request, &options, &connections, &mut warnings).await?;
+    let final_url = response.url().to_string();
+    let saved_to = download
+        .as_ref()
+        .map(|download| download.path.display().to_string());
+    let connection = connection::describe(&response, &exchange.marks, &options, &connections);
+
+    // Extract status code
+    let status_code = response.status().as_u16();
+
+    // Extract response headers, keeping duplicates and non-UTF-8 values
+    let response_headers = headers::header_entries(response.headers());
+
+    // Read the body regardless of status so 4xx/5xx payloads reach the caller,
+    // then classify it as JSON, text or binary. In download mode it goes to
+    // the file, and past the size limit to the body store or nowhere; only a
+    // preview is kept then. A body left encoded is not what Content-Type
+    // describes, so it is sniffed instead.
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let mut body = BodyReader::new(response, options.decompress, &mut warnings);
+    let content_type = content_type.filter(|_| !body.is_encoded());
+    let mut stored_body = None;
+    let (response_body, finished) = match download {
+        Some(download) => {
+            let streamed = download.write(&[], &mut body).await?;
+            let finished = Instant::now();
+            let preview =
+                ResponseBody::preview(content_type.as_deref(), &streamed.preview, streamed.size);
+            (preview, finished)
+        }
+        None => {
+            let limited = bodies
+                .read_limited(&mut body, content_type.as_deref(), &options, &mut warnings)
+                .await?;
+            let finished = Instant::now();
+            match limited {
+                LimitedBody::Complete(bytes) => {
+                    let body = ResponseBody::from_bytes(content_type.as_deref(), &bytes);
+                    (body, finished)
+                }
+                LimitedBody::Partial {
+                    preview,
+                    size,
+                    size_is_lower_bound,
+                    stored_id,
+                } => {
+                    stored_body = stored_id;
+                    let preview = ResponseBody {
+                        size_is_lower_bound,
+                        ..ResponseBody::preview(content_type.as_deref(), &preview, size)
+                    };
+                    (preview, finished)
+                }
+            }
+        }
+    };
+
+    // Calculate duration and the per-phase breakdown
+    let duration_ms = (finished - start_time).as_millis();
+    let timings = exchange.timings(body.received(), finished);
+
+    Ok(ApiResponse {
+        status_code,
+        headers: response_headers,
+        body: response_body,
+        encoding: body.encoding(),
+        duration_ms,
+        timings,
+        connection,
+        request_id,
+        request_url,
+        final_url,
+        redirects,
+        attempts,
+        saved_to,
+        stored_body,
+        warnings,
+    })
+}
+
+/// Parses the URL and appends the query parameters after any already in it,
+/// keeping repeated keys and empty values. Existing query text is kept as
+/// typed. The parameters are percent-encoded, spaces as `%20`, except for
+/// `%XX` sequences already in them, so pre-encoded values are not encoded
+/// twice. The fragment is preserved, though it is never sent.
+fn build_url(url: &str, query_params: &[(String, String)]) -> Result<Url, FetchError> {
+    let url = url.trim();
+    if url.is_empty() {
+        return Err(FetchError::new(
+            FetchErrorKind::InvalidUrl,
+            "URL cannot be empty",
+        ));
+    }
+    let mut parsed = Url::parse(url).map_err(|e| {
+        FetchError::new(
+            FetchErrorKind::InvalidUrl,
+            format!("Invalid URL {:?}: {}", url, e),
+        )
+    })?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err(FetchError::new(
+            FetchErrorKind::InvalidUrl,
+            format!("Unsupported URL scheme: {}", parsed.scheme()),
+        ));
+    }
+
+    if !query_params.is_empty() {
+        let mut query = parsed.query().unwrap_or_default().to_string();
+        for (key, value) in query_params {
+            if !query.is_empty() {
+                query.push('&');
+            }
+            query.push_str(&encode_query_component(key));
+            query.push('=');
+            query.push_str(&encode_query_component(value));
+        }
+        parsed.set_query(Some(&query));
+    }
+    Ok(parsed)
+}
+
+/// Percent-encodes a query key or value, leaving valid `%XX` escapes as
+/// they are. `&`, `=`, `+` and `#` are always encoded so the text cannot
+/// change how the query splits.
+fn encode_query_component(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut encoded = String::with_capacity(bytes.len());
+    for (index, &byte) in bytes.iter().enumerate() {
+        let escape = byte == b'%'
+            && bytes
+                .get(index + 1..index + 3)
+                .is_some_and(|hex| hex.iter().all(u8::is_ascii_hexdigit));
+        if escape || byte.is_ascii_alphanumeric() || b"-._~!$'()*,;:@/?".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+/// Accepts any RFC 9110 token as a method. Standard methods are matched
+/// case-insensitively; extension methods are sent exactly as typed, since
+/// method names are case-sensitive.
+fn parse_method(method: &str) -> Result<Method, FetchError> {
+    let method = method.trim();
+    let invalid = |reason: &str| {
+        FetchError::new(
+            FetchErrorKind::InvalidMethod,
+            format!("Invalid HTTP method {:?}: {}", method, reason),
+        )
+    };
+    if method.is_empty() {
+        return Err(invalid("method cannot be empty"));
+    }
+    if let Some(c) = method.chars().find(|&c| !is_token_char(c)) {
+        return Err(invalid(&format!("{:?} is not allowed in a method name", c)));
+    }
+
+    let standard = [
+        Method::GET,
+        Method::POST,
+        Method::PUT,
+        Method::DELETE,
+        Method::PATCH,
+        Method::HEAD,
+        Method::OPTIONS,
+        Method::TRACE,
+        Method::CONNECT,
+    ];
+    if let Some(known) = standard
+        .into_iter()
+        .find(|known| known.as_str().eq_ignore_ascii_case(method))
+    {
+        return Ok(known);
+    }
+    Method::from_bytes(method.as_bytes()).map_err(|_| invalid("not a valid token"))
+}
+
+/// `tchar` from RFC 9110 section 5.6.2.
+fn is_token_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn appends_params_after_the_existing_query() {
+        let url = build_url("https://example.com/a?x=1", &params(&[("y", "2")])).unwrap();
+        assert_eq!(url.as_str(), "https://example.com/a?x=1&y=2");
+        let url = build_url("https://example.com/a", &params(&[("y", "2")])).unwrap();
+        assert_eq!(url.as_str(), "https://example.com/a?y=2");
+    }
+
+    #[test]
+    fn keeps_repeated_keys_and_empty_values() {
+        let url = build_url(
+            "http://example.com/",
+            &params(&[("tag", "a"), ("tag", "b"), ("empty", ""), ("", "")]),
+        )
+        .unwrap();
+        assert_eq!(url.query(), Some("tag=a&tag=b&empty=&="));
+    }
+
+    #[test]
+    fn preserves_the_fragment() {
+        let url = build_url("https://example.com/p?x=1#section", &params(&[("y", "2")])).unwrap();
+        assert_eq!(url.query(), Some("x=1&y=2"));
+        assert_eq!(url.fragment(), Some("section"));
+    }
+
+    #[test]
+    fn encodes_params_once() {
+        let url = build_url(
+            "https://example.com/",
+            &params(&[
+                ("q", "a b+c&d=e#f"),
+                ("path", "a%2Fb"),
+                ("percent", "100%"),
+                ("bad", "%zz"),
+                ("name", "café"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(
+            url.query(),
+            Some("q=a%20b%2Bc%26d%3De%23f&path=a%2Fb&percent=100%25&bad=%25zz&name=caf%C3%A9")
+        );
+    }
+
+    #[test]
+    fn keeps_the_typed_query_as_is() {
+        let url = build_url("https://example.com/?a=x%2Fy&b=c+d", &[]).unwrap();
+        assert_eq!(url.query(), Some("a=x%2Fy&b=c+d"));
+    }
+
+    #[test]
+    fn rejects_bad_urls() {
+        for url in [
+            "",
+            "   ",
+            "not a url",
+            "ftp://example.com/",
+            "file:///etc/hosts",
+        ] {
+            let err = build_url(url, &[]).unwrap_err();
+            assert_eq!(err.kind, FetchErrorKind::InvalidUrl, "{:?}", url);
+        }
+    }
+
+    #[test]
+    fn standard_methods_are_case_insensitive() {
+        assert_eq!(parse_method("get").unwrap(), Method::GET);
+        assert_eq!(parse_method(" Patch ").unwrap(), Method::PATCH);
+        assert_eq!(parse_method("TRACE").unwrap(), Method::TRACE);
+    }
+
+    #[test]
+    fn extension_methods_are_kept_as_typed() {
+        assert_eq!(parse_method("PROPFIND").unwrap().as_str(), "PROPFIND");
+        assert_eq!(parse_method("Purge").unwrap().as_str(), "Purge");
+        assert_eq!(parse_method("X-CUSTOM_1!").unwrap().as_str(), "X-CUSTOM_1!");
+    }
+
+    #[test]
+    fn invalid_tokens_are_rejected() {
+        for method in ["", "  ", "GET POST", "A(B)", "Ü", "FOO/BAR"] {
+            let err = parse_method(method).unwrap_err();
+            assert_eq!(err.kind, FetchErrorKind::InvalidMethod, "{:?}", method);
+        }
+        assert!(parse_method("A B").unwrap_err().message.contains("' '"));
+    }
+}
@@ -0,0 +1,180 @@
+use crate::error::{FetchError, FetchErrorKind};
+use base64::Engine;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use serde::{Deserialize, Serialize};
+
+/// One request header line as entered by the user. Names may repeat. Headers
+/// are sent in the order given, except that hyper writes all values of a
+/// name together at its first occurrence.
+#[derive(Deserialize, Clone)]
+pub struct RequestHeader {
+    pub name: String,
+    pub value: String,
+    #[serde(default = "enabled_by_default")]
+    pub enabled: bool,
+}
+
+fn enabled_by_default() -> bool {
+    true
+}
+
+/// One response header line. Repeated headers such as `Set-Cookie` appear
+/// once per line rather than being merged.
+#[derive(Serialize)]
+pub struct HeaderEntry {
+    pub name: String,
+    /// The value decoded as UTF-8, with invalid bytes replaced.
+    pub value: String,
+    /// Base64 of the exact bytes, only present when the value is not valid
+    /// UTF-8 and `value` is therefore lossy.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub raw_base64: Option<String>,
+}
+
+/// Lists headers in received order, keeping duplicates. hyper groups repeated
+/// names under their first occurrence, so interleaving across names is not kept.
+pub fn header_entries(headers: &HeaderMap) -> Vec<HeaderEntry> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let bytes = value.as_bytes();
+            let raw_base64 = std::str::from_utf8(bytes)
+                .is_err()
+                .then(|| base64::engine::general_purpose::STANDARD.encode(bytes));
+            HeaderEntry {
+                name: name.to_string(),
+                value: String::from_utf8_lossy(bytes).into_owned(),
+                raw_base64,
+            }
+        })
+        .collect()
+}
+
+/// Validates the enabled request headers before anything is sent, so a bad
+/// name or value is reported against the entry that caused it.
+pub fn parse_request_headers(
+    headers: &[RequestHeader],
+) -> Result<Vec<(HeaderName, HeaderValue)>, FetchError> {
+    headers
+        .iter()
+        .filter(|header| header.enabled)
+        .map(|header| {
+            let name = HeaderName::from_bytes(header.name.trim().as_bytes()).map_err(|_| {
+                FetchError::new(
+                    FetchErrorKind::InvalidHeader,
+                    format!("Invalid header name: {:?}", header.name),
+                )
+            })?;
+            let value = HeaderValue::from_str(&header.value).map_err(|_| {
+                FetchError::new(
+                    FetchErrorKind::InvalidHeader,
+                    format!("Invalid value for header {}: {:?}", name, header.value),
+                )
+            })?;
+            Ok((name, value))
+        })
+        .collect()
+}
+
+/// The first header name that repeats after a different name came in
+/// between, which hyper will move up next to its first occurrence.
+pub fn first_interleaved(headers: &[(HeaderName, HeaderValue)]) -> Option<&HeaderName> {
+    headers.iter().enumerate().find_map(|(index, (name, _))| {
+        let previous = headers[..index]
+            .iter()
+            .rposition(|(seen, _)| seen == name)?;
+        (previous + 1 != index).then_some(name)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(name: &str, value: &str, enabled: bool) -> RequestHeader {
+        RequestHeader {
+            name: name.to_string(),
+            value: value.to_string(),
+            enabled,
+        }
+    }
+
+    fn names(headers: &[(HeaderName, HeaderValue)]) -> Vec<&str> {
+        headers.iter().map(|(name, _)| name.as_str()).collect()
+    }
+
+    #[test]
+    fn keeps_order_and_duplicates_and_skips_disabled() {
+        let parsed = parse_request_headers(&[
+            header("X-B", "1", true),
+            header("X-A", "2", true),
+            header("X-Skipped", "3", false),
+            header("X-B", "4", true),
+        ])
+        .unwrap();
+        assert_eq!(names(&parsed), ["x-b", "x-a", "x-b"]);
+        assert_eq!(parsed[2].1, "4");
+    }
+
+    #[test]
+    fn rejects_invalid_names_and_values() {
+        let err = parse_request_headers(&[header("Bad Name", "x", true)]).unwrap_err();
+        assert_eq!(err.kind, FetchErrorKind::InvalidHeader);
+        assert!(err.message.contains("Bad Name"));
+
+        let err = parse_request_headers(&[header("X-Ok", "line\nbreak", true)]).unwrap_err();
+        assert_eq!(err.kind, FetchErrorKind::InvalidHeader);
+
+        // Disabled entries are not validated
+        assert!(parse_request_headers(&[header("Bad Name", "x", false)]).is_ok());
+    }
+
+    #[test]
+    fn finds_interleaved_names() {
+        let parse = |names: &[&str]| {
+            let headers: Vec<_> = names.iter().map(|name| header(name, "v", true)).collect();
+            parse_request_headers(&headers).unwrap()
+        };
+        let adjacent = parse(&["A", "A", "B"]);
+        assert_eq!(first_interleaved(&adjacent), None);
+        let interleaved = parse(&["A", "B", "A"]);
+        assert_eq!(
+            first_interleaved(&interleaved).map(HeaderName::as_str),
+            Some("a")
+        );
+    }
+
+    #[test]
+    fn repeated_response_headers_are_all_listed() {
+        let mut headers = HeaderMap::new();
+        headers.append("set-cookie", HeaderValue::from_static("a=1"));
+        headers.append("content-type", HeaderValue::from_static("text/plain"));
+        headers.append("set-cookie", HeaderValue::from_static("b=2"));
+
+        let entries = header_entries(&headers);
+        let lines: Vec<(&str, &str)> = entries
+            .iter()
+            .map(|entry| (entry.name.as_str(), entry.value.as_str()))
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                ("set-cookie", "a=1"),
+                ("set-cookie", "b=2"),
+                ("content-type", "text/plain")
+            ]
+        );
+        assert!(entries.iter().all(|entry| entry.raw_base64.is_none()));
+    }
+
+    #[test]
+    fn non_utf8_values_keep_their_bytes() {
+        let mut headers = HeaderMap::new();
+        let latin1 = HeaderValue::from_bytes(b"caf\xe9").unwrap();
+        headers.insert("x-name", latin1);
+
+        let entries = header_entries(&headers);
+        assert_eq!(entries[0].value, "caf\u{FFFD}");
+        assert_eq!(entries[0].raw_base64.as_deref(), Some("Y2Fm6Q=="));
+    }
+}
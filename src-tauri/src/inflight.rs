@@ -0,0 +1,174 @@
+use crate::error::{FetchError, FetchErrorKind};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Mutex, MutexGuard};
+use tokio::task::AbortHandle;
+
+/// Requests that are currently running, keyed by their ID, so they can be
+/// aborted from `cancel_request`.
+#[derive(Default)]
+pub struct InFlightRequests {
+    tasks: Mutex<HashMap<String, AbortHandle>>,
+}
+
+impl InFlightRequests {
+    /// Runs the request on its own task and registers it under `id` until it
+    /// finishes. `started` is called once the request can be cancelled. An
+    /// ID that is already running is rejected rather than taken over, which
+    /// would leave the first request impossible to cancel.
+    pub async fn run<T, F>(
+        &self,
+        id: String,
+        future: F,
+        started: impl FnOnce(&str),
+    ) -> Result<T, FetchError>
+    where
+        F: Future<Output = Result<T, FetchError>> + Send + 'static,
+        T: Send + 'static,
+    {
+        let task = {
+            let mut tasks = self.tasks();
+            if tasks.contains_key(&id) {
+                return Err(FetchError::new(
+                    FetchErrorKind::DuplicateRequestId,
+                    format!("A request with ID {} is already running", id),
+                ));
+            }
+            let task = tokio::spawn(future);
+            tasks.insert(id.clone(), task.abort_handle());
+            task
+        };
+        let task_id = task.id();
+        started(&id);
+        let result = task.await;
+
+        // Once cancelled, the ID may have been reused by a newer request;
+        // only remove our own entry
+        {
+            let mut tasks = self.tasks();
+            if tasks.get(&id).is_some_and(|handle| handle.id() == task_id) {
+                tasks.remove(&id);
+            }
+        }
+
+        match result {
+            Ok(result) => result,
+            Err(err) if err.is_cancelled() => Err(FetchError::new(
+                FetchErrorKind::Cancelled,
+                "Request was cancelled",
+            )),
+            Err(err) => std::panic::resume_unwind(err.into_panic()),
+        }
+    }
+
+    /// Aborts the request registered under `id`, if any.
+    pub fn cancel(&self, id: &str) -> bool {
+        match self.tasks().remove(id) {
+            Some(handle) => {
+                handle.abort();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// An ID for a request whose caller did not choose one.
+    pub fn new_id() -> String {
+        format!("request-{:016x}", fastrand::u64(..))
+    }
+
+    fn tasks(&self) -> MutexGuard<'_, HashMap<String, AbortHandle>> {
+        self.tasks.lock().expect("in-flight request lock poisoned")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use tokio::sync::oneshot;
+
+    #[tokio::test]
+    async fn cancelling_fails_the_request() {
+        let requests = Arc::new(InFlightRequests::default());
+        let (started, on_started) = oneshot::channel();
+        let running = {
+            let requests = Arc::clone(&requests);
+            tokio::spawn(async move {
+                requests
+                    .run(
+                        "one".to_string(),
+                        std::future::pending::<Result<(), _>>(),
+                        |id| started.send(id.to_string()).unwrap(),
+                    )
+                    .await
+            })
+        };
+
+        assert_eq!(on_started.await.unwrap(), "one");
+        assert!(requests.cancel("one"));
+        let error = running.await.unwrap().unwrap_err();
+        assert_eq!(error.kind, FetchErrorKind::Cancelled);
+        assert!(!requests.cancel("one"));
+    }
+
+    #[test]
+    fn unknown_ids_are_not_cancelled() {
+        assert!(!InFlightRequests::default().cancel("missing"));
+    }
+
+    #[tokio::test]
+    async fn finished_requests_are_unregistered() {
+        let requests = InFlightRequests::default();
+        let result = requests
+            .run("done".to_string(), async { Ok(7) }, |_| {})
+            .await;
+        assert_eq!(result.unwrap(), 7);
+        assert!(requests.tasks().is_empty());
+
+        let error = requests
+            .run(
+                "failed".to_string(),
+                async { Err::<(), _>(FetchError::new(FetchErrorKind::Network, "reset")) },
+                |_| {},
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind, FetchErrorKind::Network);
+        assert!(requests.tasks().is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_running_id_cannot_be_reused() {
+        let requests = Arc::new(InFlightRequests::default());
+        let (started, on_started) = oneshot::channel();
+        let first = {
+            let requests = Arc::clone(&requests);
+            tokio::spawn(async move {
+                requests
+                    .run(
+                        "same".to_string(),
+                        std::future::pending::<Result<(), _>>(),
+                        |_| started.send(()).unwrap(),
+                    )
+                    .await
+            })
+        };
+        on_started.await.unwrap();
+
+        let error = requests
+            .run("same".to_string(), async { Ok(()) }, |_| {
+                panic!("a rejected request is not announced")
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind, FetchErrorKind::DuplicateRequestId);
+
+        // The first request is still the one cancel reaches
+        assert!(requests.cancel("same"));
+        assert_eq!(
+            first.await.unwrap().unwrap_err().kind,
+            FetchErrorKind::Cancelled
+        );
+    }
+}
@@ -1,15 +1,43 @@
-use serde::{Deserialize, Serialize};
-use serde_json::Value;
-use std::collections::HashMap;
-use std::time::Instant;
+mod body;
+mod certificates;
+mod client;
+mod connection;
+mod cookies;
+mod dns;
+mod download;
+mod encoding;
+mod error;
+mod fetch;
+mod headers;
+mod inflight;
+mod options;
+mod proxy;
+mod redirect;
+mod request_body;
+mod retry;
+mod storage;
+mod stored_bodies;
+#[cfg(test)]
+mod test_support;
+mod timing;
+mod tls;
 
-#[derive(Serialize, Deserialize)]
-struct ApiResponse {
-    status_code: u16,
-    headers: HashMap<String, String>,
-    body: Value,
-    duration_ms: u128,
-}
+use body::ResponseBody;
+use certificates::{CertificateStore, CertificateSummary, NewCertificate};
+use client::{ClientPool, RequestClients};
+use connection::ConnectionCache;
+use cookies::{CookieInfo, CookieJars, DEFAULT_WORKSPACE};
+use download::{Download, DownloadProgress};
+use error::FetchError;
+use fetch::{ApiResponse, FetchRequest};
+use headers::RequestHeader;
+use inflight::InFlightRequests;
+use options::{DownloadOptions, RequestOptions};
+use request_body::RequestBody;
+use std::sync::Arc;
+use stored_bodies::{StoredBodies, MAX_READ_BYTES};
+use tauri::ipc::Channel;
+use tauri::{AppHandle, Emitter, Manager};
 
 // Learn more about Tauri commands at https://tauri.app/develop/calling-rust/
 #[tauri::command]
@@ -18,115 +46,243 @@ fn greet(name: &str) -> String {
 }
 
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 async fn fetch_json(
     url: String,
     method: String,
-    headers: Option<HashMap<String, String>>,
+    headers: Option<Vec<RequestHeader>>,
     query_params: Option<Vec<(String, String)>>,
-    body: Option<Value>,
-) -> Result<ApiResponse, String> {
-    // Start timing
-    let start_time = Instant::now();
-
-    // Validate URL is not empty
-    if url.trim().is_empty() {
-        return Err("URL cannot be empty".to_string());
-    }
+    body: Option<RequestBody>,
+    options: Option<RequestOptions>,
+    request_id: Option<String>,
+    app: AppHandle,
+) -> Result<ApiResponse, FetchError> {
+    let request = FetchRequest {
+        id: request_id.unwrap_or_else(InFlightRequests::new_id),
+        url,
+        method,
+        headers,
+        query_params,
+        body,
+    };
+    send(&app, request, options, None).await
+}
 
-    // Validate method
-    let method = method.to_uppercase();
-    if !["GET", "POST", "PUT", "DELETE", "PATCH", "HEAD", "OPTIONS"].contains(&method.as_str()) {
-        return Err(format!("Unsupported HTTP method: {}", method));
-    }
+/// Like `fetch_json`, but streams the response body to `path` and reports
+/// progress on `on_progress`. The response carries only a preview of the
+/// body. Unless `timeout_ms` is given, there is no total timeout, and only
+/// a stall longer than `read_timeout_ms` (30 seconds by default) aborts it.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn download_to_file(
+    url: String,
+    method: String,
+    headers: Option<Vec<RequestHeader>>,
+    query_params: Option<Vec<(String, String)>>,
+    body: Option<RequestBody>,
+    options: Option<DownloadOptions>,
+    request_id: Option<String>,
+    path: String,
+    on_progress: Channel<DownloadProgress>,
+    app: AppHandle,
+) -> Result<ApiResponse, FetchError> {
+    let request = FetchRequest {
+        id: request_id.unwrap_or_else(InFlightRequests::new_id),
+        url,
+        method,
+        headers,
+        query_params,
+        body,
+    };
+    let download = Download {
+        path: path.into(),
+        on_progress: Box::new(move |progress| {
+            let _ = on_progress.send(progress);
+        }),
+    };
+    let options = options.unwrap_or_default().into();
+    send(&app, request, Some(options), Some(download)).await
+}
 
-    // Create HTTP client with timeout
-    let client = reqwest::Client::builder()
-        .timeout(std::time::Duration::from_secs(30))
-        .build()
-        .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
-
-    // Build URL with query parameters
-    let mut full_url = url.clone();
-    if let Some(params) = query_params {
-        if !params.is_empty() {
-            let query_string: String = params
-                .iter()
-                .map(|(k, v)| format!("{}={}", urlencoding::encode(k), urlencoding::encode(v)))
-                .collect::<Vec<_>>()
-                .join("&");
-
-            if full_url.contains('?') {
-                full_url.push('&');
-            } else {
-                full_url.push('?');
-            }
-            full_url.push_str(&query_string);
-        }
-    }
+async fn send(
+    app: &AppHandle,
+    request: FetchRequest,
+    options: Option<RequestOptions>,
+    download: Option<Download>,
+) -> Result<ApiResponse, FetchError> {
+    // Pooled HTTP clients are picked per URL, matching the request options
+    // and the certificates registered for each host along the way
+    let options = options.unwrap_or_default();
+    let cookies = Arc::clone(&app.state::<Arc<CookieJars>>());
+    let clients = RequestClients::new(
+        Arc::clone(&app.state::<Arc<ClientPool>>()),
+        Arc::clone(&cookies),
+        Arc::clone(&app.state::<Arc<CertificateStore>>()),
+        options.clone(),
+    );
+    let connections = Arc::clone(&app.state::<Arc<ConnectionCache>>());
+    let bodies = Arc::clone(&app.state::<Arc<StoredBodies>>());
 
-    // Build request based on method
-    let mut request = match method.as_str() {
-        "GET" => client.get(&full_url),
-        "POST" => client.post(&full_url),
-        "PUT" => client.put(&full_url),
-        "DELETE" => client.delete(&full_url),
-        "PATCH" => client.patch(&full_url),
-        "HEAD" => client.head(&full_url),
-        "OPTIONS" => client.request(reqwest::Method::OPTIONS, &full_url),
-        _ => return Err(format!("Unsupported method: {}", method)),
-    };
+    // Run as a tracked task so cancel_request can abort it by ID. The ID is
+    // announced once registered, since a generated one is otherwise only
+    // known once the request is over.
+    let request_id = request.id.clone();
+    let result = app
+        .state::<InFlightRequests>()
+        .run(
+            request_id,
+            fetch::execute(clients, request, options, connections, bodies, download),
+            |id| {
+                let _ = app.emit("request-started", id);
+            },
+        )
+        .await;
 
-    // Add custom headers
-    if let Some(headers_map) = headers {
-        for (key, value) in headers_map {
-            request = request.header(key, value);
-        }
+    // Cookies the responses set are written out once the request is over
+    let saved = cookies.write_changes().await;
+    let mut response = result?;
+    if let Err(err) = saved {
+        response.warn(format!("Cookies could not be saved: {}", err));
     }
+    Ok(response)
+}
 
-    // Add request body for POST/PUT/PATCH
-    if method == "POST" || method == "PUT" || method == "PATCH" {
-        if let Some(body_data) = body {
-            request = request.json(&body_data);
-        }
-    }
+/// Aborts the in-flight request with the given ID. The pending
+/// `fetch_json` or `download_to_file` call then fails with a `cancelled` error.
+/// Returns false when no request with that ID is running.
+/// Requests sent without an ID get one, announced in a `request-started`
+/// event and returned as `request_id`.
+#[tauri::command]
+fn cancel_request(id: String, in_flight: tauri::State<'_, InFlightRequests>) -> bool {
+    in_flight.cancel(&id)
+}
 
-    // Send request
-    let response = request
-        .send()
+/// Drops all pooled HTTP clients so the next request starts cold.
+/// Returns the number of clients that were discarded.
+#[tauri::command]
+fn reset_http_clients(
+    pool: tauri::State<'_, Arc<ClientPool>>,
+    connections: tauri::State<'_, Arc<ConnectionCache>>,
+) -> usize {
+    connections.clear();
+    pool.reset()
+}
+
+/// Reads part of a response body that was stored for exceeding the size
+/// limit: `length` bytes from `offset`, by default as much of the start as
+/// `MAX_READ_BYTES` allows.
+#[tauri::command]
+async fn read_response_body(
+    id: String,
+    offset: Option<u64>,
+    length: Option<u64>,
+    bodies: tauri::State<'_, Arc<StoredBodies>>,
+) -> Result<ResponseBody, FetchError> {
+    bodies
+        .read(&id, offset.unwrap_or(0), length.unwrap_or(MAX_READ_BYTES))
         .await
-        .map_err(|e| format!("Network error: {}", e))?;
+}
 
-    // Extract status code
-    let status_code = response.status().as_u16();
+/// Copies a stored response body to `path`.
+#[tauri::command]
+async fn save_response_body(
+    id: String,
+    path: String,
+    bodies: tauri::State<'_, Arc<StoredBodies>>,
+) -> Result<(), FetchError> {
+    bodies.save(&id, &path).await
+}
 
-    // Extract response headers
-    let mut response_headers = HashMap::new();
-    for (key, value) in response.headers() {
-        if let Ok(value_str) = value.to_str() {
-            response_headers.insert(key.to_string(), value_str.to_string());
-        }
-    }
+/// Deletes a stored response body. Returns false when the ID is unknown.
+#[tauri::command]
+fn discard_response_body(id: String, bodies: tauri::State<'_, Arc<StoredBodies>>) -> bool {
+    bodies.discard(&id)
+}
 
-    // Check HTTP status code
-    if !response.status().is_success() {
-        return Err(format!("HTTP error: {}", status_code));
-    }
+/// Lists the unexpired cookies in a workspace's jar, optionally only those
+/// for `domain` and its subdomains.
+#[tauri::command]
+fn list_cookies(
+    workspace: Option<String>,
+    domain: Option<String>,
+    cookies: tauri::State<'_, Arc<CookieJars>>,
+) -> Vec<CookieInfo> {
+    cookies.list(
+        workspace.as_deref().unwrap_or(DEFAULT_WORKSPACE),
+        domain.as_deref(),
+    )
+}
 
-    // Parse JSON response
-    let json_data: Value = response
-        .json()
-        .await
-        .map_err(|e| format!("Invalid JSON response: {}", e))?;
+/// Adds or replaces a cookie in a workspace's jar.
+#[tauri::command]
+async fn set_cookie(
+    workspace: Option<String>,
+    cookie: CookieInfo,
+    cookies: tauri::State<'_, Arc<CookieJars>>,
+) -> Result<(), FetchError> {
+    cookies.set(workspace.as_deref().unwrap_or(DEFAULT_WORKSPACE), cookie)?;
+    cookies.write_changes().await
+}
 
-    // Calculate duration
-    let duration_ms = start_time.elapsed().as_millis();
+/// Removes one cookie. Returns false when it was not in the jar.
+#[tauri::command]
+async fn delete_cookie(
+    workspace: Option<String>,
+    domain: String,
+    path: String,
+    name: String,
+    cookies: tauri::State<'_, Arc<CookieJars>>,
+) -> Result<bool, FetchError> {
+    let removed = cookies.delete(
+        workspace.as_deref().unwrap_or(DEFAULT_WORKSPACE),
+        &domain,
+        &path,
+        &name,
+    );
+    cookies.write_changes().await?;
+    Ok(removed)
+}
+
+/// Empties a workspace's jar, or only the cookies for `domain` and its
+/// subdomains. Returns the number of cookies removed.
+#[tauri::command]
+async fn clear_cookies(
+    workspace: Option<String>,
+    domain: Option<String>,
+    cookies: tauri::State<'_, Arc<CookieJars>>,
+) -> Result<usize, FetchError> {
+    let removed = cookies.clear(
+        workspace.as_deref().unwrap_or(DEFAULT_WORKSPACE),
+        domain.as_deref(),
+    );
+    cookies.write_changes().await?;
+    Ok(removed)
+}
 
-    Ok(ApiResponse {
-        status_code,
-        headers: response_headers,
-        body: json_data,
-        duration_ms,
-    })
+/// Lists registered CA bundles and client identities.
+#[tauri::command]
+fn list_certificates(
+    certificates: tauri::State<'_, Arc<CertificateStore>>,
+) -> Vec<CertificateSummary> {
+    certificates.list()
+}
+
+/// Registers a CA bundle or client identity read from the given files.
+#[tauri::command]
+fn add_certificate(
+    certificate: NewCertificate,
+    certificates: tauri::State<'_, Arc<CertificateStore>>,
+) -> Result<CertificateSummary, FetchError> {
+    certificates.add(certificate)
+}
+
+/// Removes a registered certificate. Returns false when the ID is unknown.
+#[tauri::command]
+fn remove_certificate(
+    id: String,
+    certificates: tauri::State<'_, Arc<CertificateStore>>,
+) -> Result<bool, FetchError> {
+    certificates.remove(&id)
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
@@ -135,7 +291,37 @@ pub fn run() {
         .plugin(tauri_plugin_opener::init())
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_fs::init())
-        .invoke_handler(tauri::generate_handler![greet, fetch_json])
+        .manage(Arc::new(ClientPool::default()))
+        .manage(InFlightRequests::default())
+        .manage(Arc::new(ConnectionCache::default()))
+        .setup(|app| {
+            // Cookies are kept with the app data so sessions survive restarts
+            let cookie_file = app.path().app_data_dir()?.join("cookies.json");
+            app.manage(Arc::new(CookieJars::load(cookie_file)));
+            let certificate_file = app.path().app_data_dir()?.join("certificates.json");
+            app.manage(Arc::new(CertificateStore::load(certificate_file)));
+            // Oversized response bodies only live until the next start
+            let body_dir = app.path().app_cache_dir()?.join("bodies");
+            app.manage(Arc::new(StoredBodies::new(body_dir)));
+            Ok(())
+        })
+        .invoke_handler(tauri::generate_handler![
+            greet,
+            fetch_json,
+            download_to_file,
+            cancel_request,
+            reset_http_clients,
+            read_response_body,
+            save_response_body,
+            discard_response_body,
+            list_cookies,
+            set_cookie,
+            delete_cookie,
+            clear_cookies,
+            list_certificates,
+            add_certificate,
+            remove_certificate
+        ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
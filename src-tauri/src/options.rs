@@ -0,0 +1,330 @@
+use crate::cookies::DEFAULT_WORKSPACE;
+use crate::error::{FetchError, FetchErrorKind};
+use crate::proxy::ProxySettings;
+use serde::{Deserialize, Deserializer};
+use std::time::Duration;
+
+/// Per-request client behaviour. Every field is optional on the wire and
+/// falls back to the defaults below.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct RequestOptions {
+    /// Limit for establishing the connection, including TLS.
+    pub connect_timeout_ms: Option<u64>,
+    /// Limit for the whole request, from connect to the last body byte.
+    pub timeout_ms: Option<u64>,
+    /// Limit for each individual read from the socket.
+    pub read_timeout_ms: Option<u64>,
+    pub follow_redirects: bool,
+    pub max_redirects: usize,
+    pub http_version: HttpVersion,
+    /// Skip certificate and hostname verification.
+    pub accept_invalid_certs: bool,
+    /// Use a new client instead of the shared pool, so the timing includes
+    /// DNS, connect and TLS rather than a reused keep-alive connection.
+    pub fresh_connection: bool,
+    /// Send cookies from the workspace's jar and store the ones received.
+    pub use_cookie_jar: bool,
+    /// Workspace whose cookie jar to use; the default one when unset.
+    pub workspace: Option<String>,
+    pub proxy: ProxySettings,
+    /// Fixed addresses for host names, which then skip DNS, like curl's
+    /// `--resolve`.
+    pub dns_overrides: Vec<DnsOverride>,
+    /// DNS server to ask instead of the system resolver, as `ip` or
+    /// `ip:port`.
+    pub dns_server: Option<String>,
+    /// Path of a Unix socket to send every request through instead of TCP,
+    /// optionally as a `unix://` URL. The request URL still supplies the
+    /// path and `Host`, and TLS is used over the socket for `https`. A
+    /// blank path is the same as none.
+    pub unix_socket: Option<String>,
+    /// Most body bytes kept in memory; unlimited when unset.
+    pub max_body_bytes: Option<u64>,
+    /// What to do with a body larger than `max_body_bytes`.
+    pub over_limit: OverLimit,
+    /// Decode compressed bodies. When off, compressed bodies are still
+    /// asked for, but returned exactly as received.
+    pub decompress: bool,
+    pub retry: RetryPolicy,
+}
+
+impl Default for RequestOptions {
+    fn default() -> Self {
+        RequestOptions {
+            connect_timeout_ms: None,
+            timeout_ms: Some(30_000),
+            read_timeout_ms: None,
+            follow_redirects: true,
+            max_redirects: 10,
+            http_version: HttpVersion::Auto,
+            accept_invalid_certs: false,
+            fresh_connection: false,
+            use_cookie_jar: true,
+            workspace: None,
+            proxy: ProxySettings::System,
+            dns_overrides: Vec::new(),
+            dns_server: None,
+            unix_socket: None,
+            max_body_bytes: Some(10 * 1024 * 1024),
+            over_limit: OverLimit::Spill,
+            decompress: true,
+            retry: RetryPolicy::default(),
+        }
+    }
+}
+
+impl RequestOptions {
+    pub fn connect_timeout(&self) -> Option<Duration> {
+        self.connect_timeout_ms.map(Duration::from_millis)
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout_ms.map(Duration::from_millis)
+    }
+
+    pub fn read_timeout(&self) -> Option<Duration> {
+        self.read_timeout_ms.map(Duration::from_millis)
+    }
+
+    /// The workspace whose cookie jar the request uses, if any.
+    pub fn cookie_workspace(&self) -> Option<&str> {
+        self.use_cookie_jar
+            .then(|| self.workspace.as_deref().unwrap_or(DEFAULT_WORKSPACE))
+    }
+
+    /// The DNS overrides as they are applied: host names and addresses
+    /// trimmed and lowercased, blank addresses left out.
+    pub fn normalized_dns_overrides(&self) -> Vec<DnsOverride> {
+        self.dns_overrides
+            .iter()
+            .map(|entry| DnsOverride {
+                host: entry.host.trim().to_ascii_lowercase(),
+                addresses: entry
+                    .addresses
+                    .iter()
+                    .map(|address| address.trim().to_ascii_lowercase())
+                    .filter(|address| !address.is_empty())
+                    .collect(),
+            })
+            .collect()
+    }
+
+    /// The DNS server, trimmed and lowercased, or `None` when blank.
+    pub fn normalized_dns_server(&self) -> Option<String> {
+        self.dns_server
+            .as_deref()
+            .map(str::trim)
+            .filter(|server| !server.is_empty())
+            .map(str::to_ascii_lowercase)
+    }
+
+    /// The Unix socket to connect through, without any `unix://` prefix,
+    /// or `None` when unset or blank. A `unix://` URL without a path names
+    /// no socket and is rejected.
+    pub fn unix_socket_path(&self) -> Result<Option<&str>, FetchError> {
+        let Some(socket) = self.unix_socket.as_deref().map(str::trim) else {
+            return Ok(None);
+        };
+        let path = socket.strip_prefix("unix://").unwrap_or(socket).trim();
+        match (path.is_empty(), socket.is_empty()) {
+            (false, _) => Ok(Some(path)),
+            (true, true) => Ok(None),
+            (true, false) => Err(FetchError::new(
+                FetchErrorKind::InvalidSocket,
+                "Unix socket path cannot be empty",
+            )),
+        }
+    }
+}
+
+/// Options for a download, which differ from `RequestOptions` only in their
+/// timeout defaults: large bodies outlast any total timeout, so unless one
+/// is given only a stall of `read_timeout_ms` aborts the download.
+#[derive(Deserialize, Default)]
+pub struct DownloadOptions {
+    #[serde(default, deserialize_with = "explicit")]
+    timeout_ms: Option<Option<u64>>,
+    #[serde(default, deserialize_with = "explicit")]
+    read_timeout_ms: Option<Option<u64>>,
+    #[serde(flatten)]
+    options: RequestOptions,
+}
+
+/// Read timeout of a download when none is given.
+const DOWNLOAD_READ_TIMEOUT_MS: u64 = 30_000;
+
+impl From<DownloadOptions> for RequestOptions {
+    fn from(download: DownloadOptions) -> Self {
+        RequestOptions {
+            timeout_ms: download.timeout_ms.unwrap_or(None),
+            read_timeout_ms: download
+                .read_timeout_ms
+                .unwrap_or(Some(DOWNLOAD_READ_TIMEOUT_MS)),
+            ..download.options
+        }
+    }
+}
+
+/// Tells a field given as `null` (`Some(None)`) from one left out (`None`).
+fn explicit<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Option<u64>>, D::Error> {
+    Option::deserialize(deserializer).map(Some)
+}
+
+/// Addresses to connect to for a host, on any port.
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct DnsOverride {
+    pub host: String,
+    pub addresses: Vec<String>,
+}
+
+/// Which HTTP version the client may use.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum HttpVersion {
+    /// Negotiate via ALPN, preferring HTTP/2 over TLS.
+    #[default]
+    Auto,
+    Http1,
+    /// HTTP/2 without negotiation: ALPN offers only `h2` over TLS, and
+    /// plain HTTP uses h2c with prior knowledge.
+    Http2,
+    /// HTTP/3 over QUIC, for `https` URLs only. It never falls back to TCP
+    /// and does not go through proxies.
+    Http3,
+}
+
+/// Handling of response bodies beyond `max_body_bytes`.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OverLimit {
+    /// Keep reading into a temporary file that can be read or saved later.
+    #[default]
+    Spill,
+    /// Stop reading and drop the rest of the body.
+    Stop,
+}
+
+/// When and how to send a request again after a transient failure. The
+/// request timeout applies to each attempt on its own.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// Attempts in total, the first included; 1 or 0 turns retrying off.
+    pub max_attempts: u32,
+    /// Response statuses that are worth another attempt.
+    pub statuses: Vec<u16>,
+    /// Failures without a response that are worth another attempt.
+    pub error_kinds: Vec<FetchErrorKind>,
+    /// Wait before the first retry. It doubles with each further one, and
+    /// a random part of up to half of it is taken off.
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    /// Wait as long as a retried response's `Retry-After` asks. A longer
+    /// wait than `max_backoff_ms` ends the retries instead.
+    pub respect_retry_after: bool,
+    /// Also retry methods that are not idempotent, such as POST, which may
+    /// then take effect more than once.
+    pub non_idempotent: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            statuses: vec![408, 429, 502, 503, 504],
+            error_kinds: vec![
+                FetchErrorKind::Dns,
+                FetchErrorKind::ConnectionRefused,
+                FetchErrorKind::Connect,
+                FetchErrorKind::Timeout,
+                FetchErrorKind::Network,
+            ],
+            initial_backoff_ms: 500,
+            max_backoff_ms: 30_000,
+            respect_retry_after: true,
+            non_idempotent: false,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn initial_backoff(&self) -> Duration {
+        Duration::from_millis(self.initial_backoff_ms)
+    }
+
+    pub fn max_backoff(&self) -> Duration {
+        Duration::from_millis(self.max_backoff_ms)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn download(json: &str) -> RequestOptions {
+        serde_json::from_str::<DownloadOptions>(json)
+            .unwrap()
+            .into()
+    }
+
+    #[test]
+    fn downloads_default_to_a_read_timeout_only() {
+        let options = download("{}");
+        assert_eq!(options.timeout_ms, None);
+        assert_eq!(options.read_timeout_ms, Some(DOWNLOAD_READ_TIMEOUT_MS));
+        assert_eq!(
+            RequestOptions::from(DownloadOptions::default()).timeout_ms,
+            None
+        );
+    }
+
+    #[test]
+    fn downloads_keep_explicit_timeouts() {
+        let options = download(r#"{"timeout_ms": 5000, "read_timeout_ms": null}"#);
+        assert_eq!(options.timeout_ms, Some(5000));
+        assert_eq!(options.read_timeout_ms, None);
+    }
+
+    fn socket(path: Option<&str>) -> RequestOptions {
+        RequestOptions {
+            unix_socket: path.map(str::to_string),
+            ..RequestOptions::default()
+        }
+    }
+
+    #[test]
+    fn blank_unix_sockets_are_unset() {
+        assert_eq!(socket(None).unix_socket_path().unwrap(), None);
+        assert_eq!(socket(Some("")).unix_socket_path().unwrap(), None);
+        assert_eq!(socket(Some("  \t")).unix_socket_path().unwrap(), None);
+    }
+
+    #[test]
+    fn unix_socket_paths_lose_the_scheme() {
+        for spelled in [
+            " unix:///run/app.sock ",
+            "unix://  /run/app.sock",
+            "/run/app.sock",
+        ] {
+            let options = socket(Some(spelled));
+            assert_eq!(options.unix_socket_path().unwrap(), Some("/run/app.sock"));
+        }
+    }
+
+    #[test]
+    fn the_scheme_alone_names_no_socket() {
+        for spelled in ["unix://", " unix://  "] {
+            let err = socket(Some(spelled)).unix_socket_path().unwrap_err();
+            assert_eq!(err.kind, FetchErrorKind::InvalidSocket);
+        }
+    }
+
+    #[test]
+    fn downloads_keep_the_other_options() {
+        let options = download(r#"{"max_redirects": 3, "decompress": false}"#);
+        assert_eq!(options.max_redirects, 3);
+        assert!(!options.decompress);
+        assert!(options.follow_redirects);
+    }
+}
@@ -0,0 +1,270 @@
+use crate::error::{FetchError, FetchErrorKind};
+use hyper_util::client::proxy::matcher::Matcher;
+use reqwest::{ClientBuilder, NoProxy, Proxy};
+use serde::Deserialize;
+use url::Url;
+
+/// How requests reach the network.
+#[derive(Deserialize, Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum ProxySettings {
+    /// Use `HTTP_PROXY`, `HTTPS_PROXY`, `ALL_PROXY` and `NO_PROXY`, plus the
+    /// OS proxy settings on macOS and Windows.
+    #[default]
+    System,
+    /// Connect directly, ignoring any system proxy.
+    None,
+    /// Explicit proxies. Each URL may use the `http`, `https`, `socks5` or
+    /// `socks5h` scheme; `socks5h` resolves hostnames on the proxy.
+    Manual {
+        /// Proxy for `http://` requests.
+        http: Option<String>,
+        /// Proxy for `https://` requests.
+        https: Option<String>,
+        username: Option<String>,
+        password: Option<String>,
+        /// Comma-separated hosts, domains and CIDR ranges to reach directly,
+        /// in the `NO_PROXY` format.
+        no_proxy: Option<String>,
+    },
+}
+
+/// Configures the client builder to use the given proxy settings.
+pub fn apply(
+    builder: ClientBuilder,
+    settings: &ProxySettings,
+) -> Result<ClientBuilder, FetchError> {
+    match settings {
+        // reqwest reads the system configuration unless told otherwise
+        ProxySettings::System => Ok(builder),
+        ProxySettings::None => Ok(builder.no_proxy()),
+        ProxySettings::Manual {
+            http,
+            https,
+            username,
+            password,
+            no_proxy,
+        } => {
+            let mut builder = builder.no_proxy();
+            for (url, for_https) in [(http, false), (https, true)] {
+                let Some(url) = url.as_deref().map(str::trim).filter(|url| !url.is_empty()) else {
+                    continue;
+                };
+                let url = proxy_url(url)?;
+                let proxy = if for_https {
+                    Proxy::https(url.as_str())
+                } else {
+                    Proxy::http(url.as_str())
+                };
+                let mut proxy = proxy.map_err(|e| {
+                    FetchError::with_context(
+                        FetchErrorKind::InvalidProxy,
+                        format_args!("Invalid proxy URL {:?}", url.as_str()),
+                        &e,
+                    )
+                })?;
+                if let Some(username) = username.as_deref().filter(|name| !name.is_empty()) {
+                    proxy = proxy.basic_auth(username, password.as_deref().unwrap_or(""));
+                }
+                proxy = proxy.no_proxy(no_proxy.as_deref().and_then(NoProxy::from_string));
+                builder = builder.proxy(proxy);
+            }
+            Ok(builder)
+        }
+    }
+}
+
+/// Names the proxy `settings` would send a request for `url` through, for
+/// warnings about requests that go around it. Follows reqwest's rules: the
+/// proxy for the URL's scheme, unless the host is exempt.
+pub fn configured(settings: &ProxySettings, url: &Url) -> Option<String> {
+    let (matcher, name) = match settings {
+        ProxySettings::System => (Matcher::from_system(), "the system proxy"),
+        ProxySettings::None => return None,
+        ProxySettings::Manual {
+            http,
+            https,
+            no_proxy,
+            ..
+        } => {
+            let proxy = |url: &Option<String>| {
+                let url = url
+                    .as_deref()
+                    .map(str::trim)
+                    .filter(|url| !url.is_empty())?;
+                proxy_url(url).ok().map(String::from)
+            };
+            let mut builder = Matcher::builder();
+            if let Some(http) = proxy(http) {
+                builder = builder.http(http);
+            }
+            if let Some(https) = proxy(https) {
+                builder = builder.https(https);
+            }
+            if let Some(no_proxy) = no_proxy {
+                builder = builder.no(no_proxy);
+            }
+            (builder.build(), "the proxy")
+        }
+    };
+    let uri = url.as_str().parse::<http::Uri>().ok()?;
+    matcher.intercept(&uri).map(|_| name.to_string())
+}
+
+/// Parses a proxy URL, taking a bare `host:port` as an HTTP proxy.
+/// reqwest would otherwise quietly reinterpret unknown schemes.
+fn proxy_url(url: &str) -> Result<Url, FetchError> {
+    let invalid = |reason: String| {
+        FetchError::new(
+            FetchErrorKind::InvalidProxy,
+            format!("Invalid proxy URL {:?}: {}", url, reason),
+        )
+    };
+    let with_scheme = if url.contains("://") {
+        url.to_string()
+    } else {
+        format!("http://{}", url)
+    };
+    let parsed = Url::parse(&with_scheme).map_err(|e| invalid(e.to_string()))?;
+    if !matches!(parsed.scheme(), "http" | "https" | "socks5" | "socks5h") {
+        return Err(invalid(format!("unsupported scheme {:?}", parsed.scheme())));
+    }
+    if parsed.host_str().is_none() {
+        return Err(invalid("missing host".to_string()));
+    }
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    fn manual(http: &str, no_proxy: Option<&str>) -> ProxySettings {
+        ProxySettings::Manual {
+            http: Some(http.to_string()),
+            https: None,
+            username: Some("user".to_string()),
+            password: Some("secret".to_string()),
+            no_proxy: no_proxy.map(str::to_string),
+        }
+    }
+
+    fn ok() -> Vec<String> {
+        vec![test_support::response("200 OK", &[], "ok")]
+    }
+
+    #[test]
+    fn manual_proxies_apply_by_scheme_and_no_proxy() {
+        let url = |url: &str| Url::parse(url).unwrap();
+        let settings = manual("127.0.0.1:3128", Some("internal.test, 10.0.0.0/8"));
+        assert_eq!(
+            configured(&settings, &url("http://example.com/")).as_deref(),
+            Some("the proxy")
+        );
+        // Only `http` is set
+        assert_eq!(configured(&settings, &url("https://example.com/")), None);
+        assert_eq!(
+            configured(&settings, &url("http://api.internal.test/")),
+            None
+        );
+        assert_eq!(configured(&settings, &url("http://10.1.2.3/")), None);
+        assert_eq!(
+            configured(&ProxySettings::None, &url("http://example.com/")),
+            None
+        );
+    }
+
+    #[test]
+    fn proxy_urls_default_to_http() {
+        assert_eq!(
+            proxy_url("127.0.0.1:3128").unwrap().as_str(),
+            "http://127.0.0.1:3128/"
+        );
+        assert_eq!(
+            proxy_url("socks5h://proxy:1080").unwrap().scheme(),
+            "socks5h"
+        );
+        assert_eq!(proxy_url("https://proxy").unwrap().scheme(), "https");
+    }
+
+    #[test]
+    fn rejects_unsupported_proxy_urls() {
+        for url in [
+            "ftp://proxy:21",
+            "socks4://proxy:1080",
+            "http://",
+            "http://[::1",
+        ] {
+            let err = proxy_url(url).unwrap_err();
+            assert_eq!(err.kind, FetchErrorKind::InvalidProxy, "{:?}", url);
+        }
+    }
+
+    #[tokio::test]
+    async fn sends_plain_http_through_the_proxy_with_credentials() {
+        let proxy = test_support::listen().await;
+        let settings = manual(&proxy.local_addr().unwrap().to_string(), None);
+        let client = apply(reqwest::Client::builder(), &settings)
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let served = tokio::spawn(test_support::serve(proxy, ok()));
+        let response = client
+            .get("http://origin.test/path?q=1")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.text().await.unwrap(), "ok");
+
+        let head = &served.await.unwrap()[0];
+        assert!(
+            head.starts_with("GET http://origin.test/path?q=1 HTTP/1.1\r\n"),
+            "{}",
+            head
+        );
+        // base64 of user:secret
+        assert!(
+            head.contains("proxy-authorization: Basic dXNlcjpzZWNyZXQ=\r\n"),
+            "{}",
+            head
+        );
+    }
+
+    #[tokio::test]
+    async fn no_proxy_hosts_are_reached_directly() {
+        let proxy = test_support::listen().await;
+        let origin = test_support::listen().await;
+        let origin_addr = origin.local_addr().unwrap();
+        let settings = manual(
+            &proxy.local_addr().unwrap().to_string(),
+            Some("example.com, 127.0.0.0/8"),
+        );
+        let client = apply(reqwest::Client::builder(), &settings)
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let served = tokio::spawn(test_support::serve(origin, ok()));
+        let response = client
+            .get(format!("http://{}/direct", origin_addr))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        assert!(served.await.unwrap()[0].starts_with("GET /direct HTTP/1.1\r\n"));
+
+        // The proxy never saw a connection
+        let accepted = tokio::time::timeout(std::time::Duration::from_millis(50), proxy.accept());
+        assert!(accepted.await.is_err());
+    }
+
+    #[test]
+    fn system_and_none_settings_need_no_validation() {
+        assert!(apply(reqwest::Client::builder(), &ProxySettings::System).is_ok());
+        assert!(apply(reqwest::Client::builder(), &ProxySettings::None).is_ok());
+        let err = apply(reqwest::Client::builder(), &manual("ftp://proxy", None)).unwrap_err();
+        assert_eq!(err.kind, FetchErrorKind::InvalidProxy);
+    }
+}
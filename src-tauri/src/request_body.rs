@@ -0,0 +1,313 @@
+use crate::error::{FetchError, FetchErrorKind};
+use reqwest::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use reqwest::multipart::{Form, Part};
+use reqwest::RequestBuilder;
+use serde::Deserialize;
+use serde_json::Value;
+
+/// Request payload as chosen in the body editor. Files are read by the
+/// backend straight from disk rather than passed through the webview.
+#[derive(Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RequestBody {
+    Json {
+        value: Value,
+    },
+    /// Text sent verbatim, e.g. XML or plain text.
+    Raw {
+        content: String,
+        content_type: Option<String>,
+    },
+    UrlEncoded {
+        fields: Vec<FormField>,
+    },
+    Multipart {
+        parts: Vec<MultipartPart>,
+    },
+    /// The contents of a file, streamed from disk.
+    Binary {
+        path: String,
+        content_type: Option<String>,
+    },
+}
+
+#[derive(Deserialize, Clone)]
+pub struct FormField {
+    pub name: String,
+    pub value: String,
+    #[serde(default = "enabled_by_default")]
+    pub enabled: bool,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum MultipartPart {
+    Text {
+        name: String,
+        value: String,
+        content_type: Option<String>,
+    },
+    /// A file part. The file name and content type default to the ones
+    /// derived from the path.
+    File {
+        name: String,
+        path: String,
+        file_name: Option<String>,
+        content_type: Option<String>,
+    },
+}
+
+fn enabled_by_default() -> bool {
+    true
+}
+
+impl RequestBody {
+    /// Attaches the body and a matching `Content-Type` to the request. Custom
+    /// headers are applied afterwards, so an explicit `Content-Type` wins.
+    pub async fn apply(&self, request: RequestBuilder) -> Result<RequestBuilder, FetchError> {
+        match self {
+            RequestBody::Json { value } => Ok(request.json(value)),
+            RequestBody::Raw {
+                content,
+                content_type,
+            } => {
+                let content_type = content_type
+                    .as_deref()
+                    .unwrap_or("text/plain; charset=utf-8");
+                Ok(request
+                    .header(CONTENT_TYPE, content_type)
+                    .body(content.clone()))
+            }
+            RequestBody::UrlEncoded { fields } => {
+                let pairs: Vec<(&str, &str)> = fields
+                    .iter()
+                    .filter(|field| field.enabled)
+                    .map(|field| (field.name.as_str(), field.value.as_str()))
+                    .collect();
+                Ok(request.form(&pairs))
+            }
+            RequestBody::Multipart { parts } => {
+                let mut form = Form::new();
+                for part in parts {
+                    let (name, part) = multipart_part(part).await?;
+                    form = form.part(name, part);
+                }
+                Ok(request.multipart(form))
+            }
+            RequestBody::Binary { path, content_type } => {
+                let file = tokio::fs::File::open(path)
+                    .await
+                    .map_err(|e| file_error(path, &e))?;
+                let length = file
+                    .metadata()
+                    .await
+                    .map_err(|e| file_error(path, &e))?
+                    .len();
+                let content_type = content_type
+                    .as_deref()
+                    .unwrap_or("application/octet-stream");
+                Ok(request
+                    .header(CONTENT_TYPE, content_type)
+                    .header(CONTENT_LENGTH, length)
+                    .body(file))
+            }
+        }
+    }
+}
+
+async fn multipart_part(part: &MultipartPart) -> Result<(String, Part), FetchError> {
+    match part {
+        MultipartPart::Text {
+            name,
+            value,
+            content_type,
+        } => {
+            let mut part = Part::text(value.clone());
+            if let Some(content_type) = content_type {
+                part = with_mime(part, content_type)?;
+            }
+            Ok((name.clone(), part))
+        }
+        MultipartPart::File {
+            name,
+            path,
+            file_name,
+            content_type,
+        } => {
+            let mut part = Part::file(path).await.map_err(|e| file_error(path, &e))?;
+            if let Some(file_name) = file_name {
+                part = part.file_name(file_name.clone());
+            }
+            if let Some(content_type) = content_type {
+                part = with_mime(part, content_type)?;
+            }
+            Ok((name.clone(), part))
+        }
+    }
+}
+
+fn with_mime(part: Part, content_type: &str) -> Result<Part, FetchError> {
+    part.mime_str(content_type).map_err(|_| {
+        FetchError::new(
+            FetchErrorKind::RequestBody,
+            format!(
+                "Invalid content type for multipart part: {:?}",
+                content_type
+            ),
+        )
+    })
+}
+
+fn file_error(path: &str, err: &std::io::Error) -> FetchError {
+    FetchError::with_context(
+        FetchErrorKind::RequestBody,
+        format_args!("Failed to read {}", path),
+        err,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_file;
+    use http_body_util::BodyExt;
+
+    async fn build(body: RequestBody) -> Result<reqwest::Request, FetchError> {
+        let request = reqwest::Client::new().post("http://example.test/");
+        Ok(body.apply(request).await?.build().unwrap())
+    }
+
+    fn header<'a>(request: &'a reqwest::Request, name: &str) -> Option<&'a str> {
+        request
+            .headers()
+            .get(name)
+            .map(|value| value.to_str().unwrap())
+    }
+
+    async fn sent(request: reqwest::Request) -> String {
+        let request = http::Request::<reqwest::Body>::try_from(request).unwrap();
+        let bytes = request.into_body().collect().await.unwrap().to_bytes();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn raw_text_defaults_to_utf8_plain_text() {
+        let request = build(RequestBody::Raw {
+            content: "<ok/>".to_string(),
+            content_type: None,
+        })
+        .await
+        .unwrap();
+        assert_eq!(
+            header(&request, "content-type"),
+            Some("text/plain; charset=utf-8")
+        );
+        assert_eq!(sent(request).await, "<ok/>");
+
+        let request = build(RequestBody::Raw {
+            content: "<ok/>".to_string(),
+            content_type: Some("application/xml".to_string()),
+        })
+        .await
+        .unwrap();
+        assert_eq!(header(&request, "content-type"), Some("application/xml"));
+    }
+
+    #[tokio::test]
+    async fn disabled_form_fields_are_left_out() {
+        let field = |name: &str, value: &str, enabled| FormField {
+            name: name.to_string(),
+            value: value.to_string(),
+            enabled,
+        };
+        let request = build(RequestBody::UrlEncoded {
+            fields: vec![
+                field("a", "1", true),
+                field("b", "2", false),
+                field("c", "x y", true),
+            ],
+        })
+        .await
+        .unwrap();
+        assert_eq!(
+            header(&request, "content-type"),
+            Some("application/x-www-form-urlencoded")
+        );
+        assert_eq!(sent(request).await, "a=1&c=x+y");
+    }
+
+    #[tokio::test]
+    async fn binary_bodies_are_sized_from_the_file() {
+        let request = build(RequestBody::Binary {
+            path: temp_file("0123456789"),
+            content_type: None,
+        })
+        .await
+        .unwrap();
+        assert_eq!(
+            header(&request, "content-type"),
+            Some("application/octet-stream")
+        );
+        assert_eq!(header(&request, "content-length"), Some("10"));
+        assert_eq!(sent(request).await, "0123456789");
+    }
+
+    #[tokio::test]
+    async fn multipart_files_take_the_given_name_and_type() {
+        let path = temp_file("a,b\n1,2\n");
+        let request = build(RequestBody::Multipart {
+            parts: vec![
+                MultipartPart::Text {
+                    name: "note".to_string(),
+                    value: "hi".to_string(),
+                    content_type: None,
+                },
+                MultipartPart::File {
+                    name: "upload".to_string(),
+                    path: path.clone(),
+                    file_name: Some("report.csv".to_string()),
+                    content_type: Some("text/csv".to_string()),
+                },
+            ],
+        })
+        .await
+        .unwrap();
+        assert!(header(&request, "content-type")
+            .unwrap()
+            .starts_with("multipart/form-data; boundary="));
+
+        let body = sent(request).await;
+        assert!(body.contains("Content-Disposition: form-data; name=\"note\"\r\n\r\nhi\r\n"));
+        assert!(body.contains(
+            "Content-Disposition: form-data; name=\"upload\"; filename=\"report.csv\"\r\n\
+             Content-Type: text/csv\r\n\r\na,b\n1,2\n"
+        ));
+    }
+
+    #[tokio::test]
+    async fn missing_files_are_request_body_errors() {
+        let missing = std::env::temp_dir().join(format!("missing-{:016x}", fastrand::u64(..)));
+        let missing = missing.display().to_string();
+        let bodies = [
+            RequestBody::Binary {
+                path: missing.clone(),
+                content_type: None,
+            },
+            RequestBody::Multipart {
+                parts: vec![MultipartPart::File {
+                    name: "upload".to_string(),
+                    path: missing.clone(),
+                    file_name: None,
+                    content_type: None,
+                }],
+            },
+        ];
+        for body in bodies {
+            let err = build(body).await.unwrap_err();
+            assert_eq!(err.kind, FetchErrorKind::RequestBody);
+            assert!(err
+                .message
+                .starts_with(&format!("Failed to read {}: ", missing)));
+        }
+    }
+}
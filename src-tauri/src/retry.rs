@@ -0,0 +1,357 @@
+use crate::client::RequestClients;
+use crate::connection::ConnectionCache;
+use crate::error::FetchError;
+use crate::options::{RequestOptions, RetryPolicy};
+use crate::redirect::{self, Exchange, RedirectHop};
+use crate::timing;
+use reqwest::header::RETRY_AFTER;
+use reqwest::{Method, Request, Response};
+use serde::Serialize;
+use std::time::{Duration, Instant, SystemTime};
+
+/// One try at the request and how it ended.
+#[derive(Serialize, Clone, Debug)]
+pub struct Attempt {
+    /// Status of the response the attempt ended with, after redirects.
+    status_code: Option<u16>,
+    /// Why the attempt got no response.
+    error: Option<FetchError>,
+    /// From sending to the response head, or to the failure.
+    duration_ms: f64,
+    /// Wait before the next attempt; absent on the last one.
+    delay_ms: Option<f64>,
+    /// Warnings raised while sending this attempt. Only the last attempt's
+    /// also appear with the response.
+    warnings: Vec<String>,
+}
+
+/// Sends the request, following redirects, and sends it again after the
+/// responses and failures the retry policy names. A failure that ends the
+/// retries is returned with the number of attempts made and all of them.
+pub async fn send(
+    clients: &RequestClients,
+    mut request: Request,
+    options: &RequestOptions,
+    connections: &ConnectionCache,
+    warnings: &mut Vec<String>,
+) -> Result<(Response, Exchange, Vec<RedirectHop>, Vec<Attempt>), FetchError> {
+    let policy = &options.retry;
+    let method = request.method().clone();
+    let mut attempts = Vec::new();
+    loop {
+        let number = attempts.len() as u32 + 1;
+        // Keep a copy to send again, unless this is the last attempt
+        let spare = if number < policy.max_attempts {
+            request.try_clone()
+        } else {
+            None
+        };
+        let started = Instant::now();
+        let mut attempt_warnings = Vec::new();
+        let outcome = redirect::follow(
+            clients,
+            request,
+            options,
+            connections,
+            &mut attempt_warnings,
+        )
+        .await;
+        let duration_ms = timing::millis(started.elapsed());
+
+        let retryable = match &outcome {
+            Ok((response, ..)) => policy.statuses.contains(&response.status().as_u16()),
+            Err(err) => policy.error_kinds.contains(&err.kind),
+        };
+        let next = if retryable && number < policy.max_attempts {
+            next_attempt(
+                policy,
+                &method,
+                spare,
+                &outcome,
+                number,
+                &mut attempt_warnings,
+            )
+        } else {
+            None
+        };
+        let Some((next, delay)) = next else {
+            warnings.extend(attempt_warnings.iter().cloned());
+            let mut last = Attempt {
+                status_code: None,
+                error: None,
+                duration_ms,
+                delay_ms: None,
+                warnings: attempt_warnings,
+            };
+            return match outcome {
+                Ok((response, exchange, hops)) => {
+                    last.status_code = Some(response.status().as_u16());
+                    attempts.push(last);
+                    Ok((response, exchange, hops, attempts))
+                }
+                Err(mut err) => {
+                    last.error = Some(err.clone());
+                    attempts.push(last);
+                    if number > 1 {
+                        err.message =
+                            format!("{} (gave up after {} attempts)", err.message, number);
+                    }
+                    err.attempts = attempts;
+                    Err(err)
+                }
+            };
+        };
+
+        // Dropping the response unread closes its connection
+        attempts.push(Attempt {
+            status_code: outcome
+                .as_ref()
+                .ok()
+                .map(|(response, ..)| response.status().as_u16()),
+            error: outcome.err(),
+            duration_ms,
+            delay_ms: Some(timing::millis(delay)),
+            warnings: attempt_warnings,
+        });
+        tokio::time::sleep(delay).await;
+        request = next;
+    }
+}
+
+/// The request to send next and how long to wait first, or `None` with a
+/// warning when the failed attempt may not be repeated.
+fn next_attempt(
+    policy: &RetryPolicy,
+    method: &Method,
+    spare: Option<Request>,
+    outcome: &Result<(Response, Exchange, Vec<RedirectHop>), FetchError>,
+    number: u32,
+    warnings: &mut Vec<String>,
+) -> Option<(Request, Duration)> {
+    if !policy.non_idempotent && !is_idempotent(method) {
+        warnings.push(format!(
+            "Did not retry the {}: it is not idempotent and could take effect twice",
+            method
+        ));
+        return None;
+    }
+    let Some(spare) = spare else {
+        warnings.push(
+            "Did not retry: the request body was streamed and cannot be sent again".to_string(),
+        );
+        return None;
+    };
+
+    let requested = match outcome {
+        Ok((response, ..)) if policy.respect_retry_after => retry_after(response),
+        _ => None,
+    };
+    let delay = match requested {
+        Some(wait) if wait > policy.max_backoff() => {
+            warnings.push(format!(
+                "Did not retry: the server asked to wait {} ms, more than the {} ms maximum backoff",
+                wait.as_millis(),
+                policy.max_backoff_ms
+            ));
+            return None;
+        }
+        Some(wait) => wait,
+        None => backoff(policy, number),
+    };
+    Some((spare, delay))
+}
+
+/// Exponential backoff after attempt `number`, less a random part of up to
+/// half so that clients failing together do not retry together.
+fn backoff(policy: &RetryPolicy, number: u32) -> Duration {
+    let base = policy
+        .initial_backoff()
+        .saturating_mul(2u32.saturating_pow(number - 1))
+        .min(policy.max_backoff());
+    let jitter = fastrand::u64(..=base.as_millis() as u64 / 2);
+    base.saturating_sub(Duration::from_millis(jitter))
+}
+
+/// The wait a `Retry-After` header asks for, given in seconds or as a date.
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(date.duration_since(SystemTime::now()).unwrap_or_default())
+}
+
+/// Methods RFC 9110 defines as idempotent.
+fn is_idempotent(method: &Method) -> bool {
+    [
+        Method::GET,
+        Method::HEAD,
+        Method::PUT,
+        Method::DELETE,
+        Method::OPTIONS,
+        Method::TRACE,
+    ]
+    .contains(method)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::FetchErrorKind;
+    use crate::test_support;
+    use reqwest::header::HeaderValue;
+    use url::Url;
+
+    fn policy(initial_backoff_ms: u64, max_backoff_ms: u64) -> RetryPolicy {
+        RetryPolicy {
+            initial_backoff_ms,
+            max_backoff_ms,
+            ..RetryPolicy::default()
+        }
+    }
+
+    fn with_retry_after(value: &str) -> Response {
+        let response = http::Response::builder()
+            .header(RETRY_AFTER, HeaderValue::from_str(value).unwrap())
+            .body("")
+            .unwrap();
+        Response::from(response)
+    }
+
+    #[test]
+    fn backoff_doubles_with_up_to_half_taken_off() {
+        let policy = policy(100, 1_000);
+        for _ in 0..50 {
+            for (number, base) in [(1, 100), (2, 200), (3, 400), (4, 800), (5, 1_000)] {
+                let delay = backoff(&policy, number).as_millis() as u64;
+                assert!(
+                    (base / 2..=base).contains(&delay),
+                    "attempt {} waited {} ms",
+                    number,
+                    delay
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn backoff_saturates_at_the_maximum() {
+        let delay = backoff(&policy(500, 30_000), 200);
+        assert!(delay >= Duration::from_secs(15) && delay <= Duration::from_secs(30));
+        assert_eq!(backoff(&policy(0, 30_000), 3), Duration::ZERO);
+    }
+
+    #[test]
+    fn retry_after_in_seconds() {
+        assert_eq!(
+            retry_after(&with_retry_after("120")),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(retry_after(&with_retry_after(" 0 ")), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn retry_after_as_a_date() {
+        let date = httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(60));
+        let wait = retry_after(&with_retry_after(&date)).unwrap();
+        // The date only has whole seconds
+        assert!(wait > Duration::from_secs(58) && wait <= Duration::from_secs(60));
+
+        let past = httpdate::fmt_http_date(SystemTime::now() - Duration::from_secs(60));
+        assert_eq!(retry_after(&with_retry_after(&past)), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn unusable_retry_after_is_ignored() {
+        assert_eq!(retry_after(&with_retry_after("soon")), None);
+        assert_eq!(retry_after(&with_retry_after("-5")), None);
+        let response = Response::from(http::Response::new(""));
+        assert_eq!(retry_after(&response), None);
+    }
+
+    fn options(max_attempts: u32, statuses: Vec<u16>) -> RequestOptions {
+        RequestOptions {
+            retry: RetryPolicy {
+                max_attempts,
+                statuses,
+                initial_backoff_ms: 1,
+                max_backoff_ms: 10,
+                ..RetryPolicy::default()
+            },
+            ..RequestOptions::default()
+        }
+    }
+
+    async fn send_to(
+        url: &Url,
+        options: &RequestOptions,
+        warnings: &mut Vec<String>,
+    ) -> Result<(Response, Exchange, Vec<RedirectHop>, Vec<Attempt>), FetchError> {
+        let clients = test_support::clients(options);
+        let request = clients.for_url(url)?.get(url.clone()).build()?;
+        send(
+            &clients,
+            request,
+            options,
+            &ConnectionCache::default(),
+            warnings,
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn zero_attempts_sends_once() {
+        let listener = test_support::listen().await;
+        let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        let unavailable = test_support::response("503 Service Unavailable", &[], "");
+        let server = tokio::spawn(test_support::serve(listener, vec![unavailable]));
+
+        let options = options(0, vec![503]);
+        let (response, _, _, attempts) = send_to(&url, &options, &mut Vec::new()).await.unwrap();
+        assert_eq!(response.status(), 503);
+        assert_eq!(attempts.len(), 1);
+        assert_eq!(server.await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn warnings_stay_with_their_attempt() {
+        let listener = test_support::listen().await;
+        let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        // A redirect that cannot be followed warns, then is retried
+        let unfollowable = test_support::response("302 Found", &[("Location", "ftp://x/")], "");
+        let ok = test_support::response("200 OK", &[], "ok");
+        let server = tokio::spawn(test_support::serve(listener, vec![unfollowable, ok]));
+
+        let mut warnings = Vec::new();
+        let options = options(3, vec![302]);
+        let (response, _, _, attempts) = send_to(&url, &options, &mut warnings).await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        assert_eq!(attempts.len(), 2);
+        assert_eq!(attempts[0].status_code, Some(302));
+        assert_eq!(attempts[0].warnings.len(), 1);
+        assert!(attempts[1].warnings.is_empty());
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn failures_keep_every_attempt() {
+        // Nothing listens on the port once the listener is dropped
+        let port = test_support::listen().await.local_addr().unwrap().port();
+        let url = Url::parse(&format!("http://127.0.0.1:{}/", port)).unwrap();
+
+        let err = send_to(&url, &options(3, vec![]), &mut Vec::new())
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.kind, FetchErrorKind::ConnectionRefused);
+        assert!(err.message.ends_with("(gave up after 3 attempts)"));
+        assert_eq!(err.attempts.len(), 3);
+        for (index, attempt) in err.attempts.iter().enumerate() {
+            let kind = attempt.error.as_ref().map(|err| err.kind);
+            assert_eq!(kind, Some(FetchErrorKind::ConnectionRefused));
+            assert_eq!(attempt.delay_ms.is_some(), index < 2);
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use crate::error::{FetchError, FetchErrorKind};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+
+/// Reads a JSON file written by `write_json`. A missing or unreadable file
+/// yields `None` so callers can start empty.
+pub fn read_json<T: DeserializeOwned>(file: &Path) -> Option<T> {
+    let bytes = std::fs::read(file).ok()?;
+    serde_json::from_slice(&bytes).ok()
+}
+
+/// Writes `value` as JSON, going through a temporary file and a rename so a
+/// crash never leaves a truncated file behind. The stored files hold private
+/// keys and session cookies, so on Unix only the owner may read them.
+pub fn write_json<T: Serialize>(file: &Path, value: &T) -> Result<(), FetchError> {
+    let storage_error = |e: &(dyn std::error::Error + 'static)| {
+        FetchError::with_context(
+            FetchErrorKind::Storage,
+            format_args!("Failed to write {}", file.display()),
+            e,
+        )
+    };
+    let json = serde_json::to_vec_pretty(value).map_err(|e| storage_error(&e))?;
+    if let Some(dir) = file.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| storage_error(&e))?;
+    }
+    let partial = file.with_extension("partial");
+    // A leftover from a crash would keep its permissions
+    let _ = std::fs::remove_file(&partial);
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut writer = options.open(&partial).map_err(|e| storage_error(&e))?;
+    writer.write_all(&json).map_err(|e| storage_error(&e))?;
+    drop(writer);
+    std::fs::rename(&partial, file).map_err(|e| storage_error(&e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir() -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("storage-{:016x}", fastrand::u64(..)))
+    }
+
+    #[test]
+    fn round_trips_json() {
+        let file = temp_dir().join("values.json");
+        write_json(&file, &vec![1, 2, 3]).unwrap();
+        assert_eq!(read_json::<Vec<u32>>(&file), Some(vec![1, 2, 3]));
+        assert!(!file.with_extension("partial").exists());
+        assert_eq!(
+            read_json::<Vec<u32>>(&temp_dir().join("missing.json")),
+            None
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn only_the_owner_can_read_the_file() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let file = temp_dir().join("secrets.json");
+        // Even over a readable leftover from an interrupted write
+        std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+        std::fs::write(file.with_extension("partial"), "{").unwrap();
+        std::fs::write(&file, "[]").unwrap();
+        write_json(&file, &"key").unwrap();
+
+        let mode = std::fs::metadata(&file).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(read_json::<String>(&file).as_deref(), Some("key"));
+    }
+}
@@ -0,0 +1,352 @@
+use crate::body::ResponseBody;
+use crate::download::{Download, PREVIEW_BYTES};
+use crate::encoding::BodyReader;
+use crate::error::{FetchError, FetchErrorKind};
+use crate::options::{OverLimit, RequestOptions};
+use std::collections::HashMap;
+use std::io::SeekFrom;
+use std::path::PathBuf;
+use std::sync::{Mutex, MutexGuard};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+/// Most bytes of a stored body `StoredBodies::read` returns at once; the
+/// whole of a larger body can only be saved to a file.
+pub const MAX_READ_BYTES: u64 = 16 * 1024 * 1024;
+
+/// Response bodies that went over `max_body_bytes`, kept as temporary files
+/// until they are discarded or the app restarts.
+pub struct StoredBodies {
+    dir: PathBuf,
+    bodies: Mutex<HashMap<String, StoredBody>>,
+}
+
+struct StoredBody {
+    path: PathBuf,
+    content_type: Option<String>,
+}
+
+/// A response body as read under the size limit.
+pub enum LimitedBody {
+    Complete(Vec<u8>),
+    /// Only the start is in memory. The whole body is in the store under
+    /// `stored_id`, or was dropped when that is `None`.
+    Partial {
+        preview: Vec<u8>,
+        /// Size of the whole body, or of what was read of it when it was
+        /// dropped without a known length.
+        size: u64,
+        size_is_lower_bound: bool,
+        stored_id: Option<String>,
+    },
+}
+
+impl StoredBodies {
+    /// Keeps bodies in `dir`, deleting any left over from an earlier run.
+    pub fn new(dir: PathBuf) -> Self {
+        let _ = std::fs::remove_dir_all(&dir);
+        StoredBodies {
+            dir,
+            bodies: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Reads the body, holding at most `max_body_bytes` of it in memory.
+    /// Past the limit the body is either spilled to a file in the store or
+    /// abandoned, as `over_limit` says.
+    pub async fn read_limited(
+        &self,
+        body: &mut BodyReader,
+        content_type: Option<&str>,
+        options: &RequestOptions,
+        warnings: &mut Vec<String>,
+    ) -> Result<LimitedBody, FetchError> {
+        let limit = options.max_body_bytes.unwrap_or(u64::MAX);
+        let preview_len = PREVIEW_BYTES.min(usize::try_from(limit).unwrap_or(usize::MAX));
+        let announced = body.decoded_length();
+
+        let mut buffer = Vec::new();
+        while let Some(chunk) = body.chunk().await? {
+            buffer.extend_from_slice(&chunk);
+            if buffer.len() as u64 <= limit {
+                continue;
+            }
+
+            return match options.over_limit {
+                OverLimit::Stop => {
+                    warnings.push(format!(
+                        "Stopped reading the body after {} bytes as it exceeds the {} byte \
+                         limit; the rest was discarded",
+                        buffer.len(),
+                        limit
+                    ));
+                    let size = announced.unwrap_or(buffer.len() as u64);
+                    buffer.truncate(preview_len);
+                    Ok(LimitedBody::Partial {
+                        preview: buffer,
+                        size,
+                        size_is_lower_bound: announced.is_none(),
+                        stored_id: None,
+                    })
+                }
+                OverLimit::Spill => {
+                    let id = self.new_id();
+                    let path = self.dir.join(&id);
+                    tokio::fs::create_dir_all(&self.dir).await.map_err(|e| {
+                        storage_error(format!("Failed to create {}", self.dir.display()), &e)
+                    })?;
+                    let download = Download {
+                        path: path.clone(),
+                        on_progress: Box::new(|_| {}),
+                    };
+                    let mut streamed = download.write(&buffer, body).await?;
+                    let content_type = content_type.map(str::to_string);
+                    self.lock()
+                        .insert(id.clone(), StoredBody { path, content_type });
+                    streamed.preview.truncate(preview_len);
+                    Ok(LimitedBody::Partial {
+                        preview: streamed.preview,
+                        size: streamed.size,
+                        size_is_lower_bound: false,
+                        stored_id: Some(id),
+                    })
+                }
+            };
+        }
+        Ok(LimitedBody::Complete(buffer))
+    }
+
+    /// Reads up to `length` bytes of a stored body from `offset`, and no
+    /// more than `MAX_READ_BYTES`. The range is described like a preview:
+    /// `size_bytes` is the size of the whole body and `truncated` tells
+    /// whether more of it follows the range.
+    pub async fn read(
+        &self,
+        id: &str,
+        offset: u64,
+        length: u64,
+    ) -> Result<ResponseBody, FetchError> {
+        let (path, content_type) = {
+            let bodies = self.lock();
+            let body = bodies.get(id).ok_or_else(|| unknown(id))?;
+            (body.path.clone(), body.content_type.clone())
+        };
+        let read_error = |e| storage_error(format!("Failed to read {}", path.display()), &e);
+        let mut file = tokio::fs::File::open(&path).await.map_err(read_error)?;
+        let size = file.metadata().await.map_err(read_error)?.len();
+        let offset = offset.min(size);
+        file.seek(SeekFrom::Start(offset))
+            .await
+            .map_err(read_error)?;
+        let mut bytes = Vec::new();
+        file.take(length.min(MAX_READ_BYTES))
+            .read_to_end(&mut bytes)
+            .await
+            .map_err(read_error)?;
+        Ok(ResponseBody {
+            size_bytes: size,
+            ..ResponseBody::preview(content_type.as_deref(), &bytes, size - offset)
+        })
+    }
+
+    /// Copies a stored body to `target`. It stays in the store.
+    pub async fn save(&self, id: &str, target: &str) -> Result<(), FetchError> {
+        let path = self.lock().get(id).ok_or_else(|| unknown(id))?.path.clone();
+        tokio::fs::copy(&path, target)
+            .await
+            .map_err(|e| storage_error(format!("Failed to write {}", target), &e))?;
+        Ok(())
+    }
+
+    /// Deletes a stored body. Returns false when the ID is unknown.
+    pub fn discard(&self, id: &str) -> bool {
+        match self.lock().remove(id) {
+            Some(body) => {
+                let _ = std::fs::remove_file(body.path);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// A random ID that no stored body has yet.
+    fn new_id(&self) -> String {
+        let bodies = self.lock();
+        loop {
+            let id = format!("{:016x}", fastrand::u64(..));
+            if !bodies.contains_key(&id) {
+                return id;
+            }
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, StoredBody>> {
+        self.bodies.lock().expect("stored bodies lock poisoned")
+    }
+}
+
+fn unknown(id: &str) -> FetchError {
+    FetchError::new(
+        FetchErrorKind::Storage,
+        format!("No stored response body with ID {:?}", id),
+    )
+}
+
+fn storage_error(context: String, err: &std::io::Error) -> FetchError {
+    FetchError::with_context(FetchErrorKind::Storage, context, err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::BodyContent;
+    use crate::test_support::body_reader;
+
+    fn empty_store() -> StoredBodies {
+        StoredBodies::new(std::env::temp_dir().join(format!("bodies-{:016x}", fastrand::u64(..))))
+    }
+
+    fn store(contents: &str) -> (StoredBodies, String) {
+        let bodies = empty_store();
+        let dir = bodies.dir.clone();
+        std::fs::create_dir_all(&dir).unwrap();
+        let id = bodies.new_id();
+        let path = dir.join(&id);
+        std::fs::write(&path, contents).unwrap();
+        bodies.lock().insert(
+            id.clone(),
+            StoredBody {
+                path,
+                content_type: Some("text/plain".to_string()),
+            },
+        );
+        (bodies, id)
+    }
+
+    fn text(body: &ResponseBody) -> &str {
+        match &body.content {
+            BodyContent::Text { text } => text,
+            _ => panic!("not text"),
+        }
+    }
+
+    #[tokio::test]
+    async fn reads_a_range() {
+        let (bodies, id) = store("0123456789");
+
+        let start = bodies.read(&id, 0, 4).await.unwrap();
+        assert_eq!(text(&start), "0123");
+        assert_eq!(start.size_bytes, 10);
+        assert!(start.truncated);
+
+        let end = bodies.read(&id, 6, 100).await.unwrap();
+        assert_eq!(text(&end), "6789");
+        assert!(!end.truncated);
+
+        let past = bodies.read(&id, 20, 4).await.unwrap();
+        assert_eq!(past.size_bytes, 10);
+        assert!(matches!(past.content, BodyContent::Empty));
+    }
+
+    #[tokio::test]
+    async fn unknown_ids_are_storage_errors() {
+        let (bodies, id) = store("body");
+        assert!(bodies.discard(&id));
+        let err = bodies.read(&id, 0, 4).await.err().unwrap();
+        assert_eq!(err.kind, FetchErrorKind::Storage);
+        assert!(!bodies.discard(&id));
+    }
+
+    fn limited(over_limit: OverLimit) -> RequestOptions {
+        RequestOptions {
+            max_body_bytes: Some(6),
+            over_limit,
+            ..RequestOptions::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn bodies_within_the_limit_are_complete() {
+        let mut body = body_reader(["abc", "def"], false);
+        let mut warnings = Vec::new();
+        let limited = empty_store()
+            .read_limited(&mut body, None, &limited(OverLimit::Stop), &mut warnings)
+            .await
+            .unwrap();
+        assert!(matches!(limited, LimitedBody::Complete(bytes) if bytes == b"abcdef"));
+        assert!(warnings.is_empty());
+    }
+
+    #[tokio::test]
+    async fn stopping_without_a_length_reports_a_lower_bound() {
+        let mut body = body_reader(["aaaa", "bbbb", "cccc"], false);
+        let mut warnings = Vec::new();
+        let limited = empty_store()
+            .read_limited(&mut body, None, &limited(OverLimit::Stop), &mut warnings)
+            .await
+            .unwrap();
+
+        let LimitedBody::Partial {
+            preview,
+            size,
+            size_is_lower_bound,
+            stored_id,
+        } = limited
+        else {
+            panic!("expected a partial body");
+        };
+        assert_eq!(preview, b"aaaabb");
+        assert_eq!((size, size_is_lower_bound), (8, true));
+        assert_eq!(stored_id, None);
+        // The rest was never read
+        assert_eq!(body.received(), 8);
+        assert!(warnings[0].starts_with("Stopped reading the body after 8 bytes"));
+    }
+
+    #[tokio::test]
+    async fn stopping_with_a_length_reports_the_whole_size() {
+        let mut body = body_reader(["aaaabbbbcccc"], true);
+        let limited = empty_store()
+            .read_limited(&mut body, None, &limited(OverLimit::Stop), &mut Vec::new())
+            .await
+            .unwrap();
+        assert!(matches!(
+            limited,
+            LimitedBody::Partial {
+                size: 12,
+                size_is_lower_bound: false,
+                stored_id: None,
+                ..
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn spilling_stores_the_whole_body() {
+        let bodies = empty_store();
+        let mut body = body_reader(["aaaa", "bbbb", "cccc"], false);
+        let limited = bodies
+            .read_limited(
+                &mut body,
+                Some("text/plain"),
+                &limited(OverLimit::Spill),
+                &mut Vec::new(),
+            )
+            .await
+            .unwrap();
+
+        let LimitedBody::Partial {
+            preview,
+            size,
+            size_is_lower_bound,
+            stored_id: Some(id),
+        } = limited
+        else {
+            panic!("expected a stored body");
+        };
+        assert_eq!(preview, b"aaaabb");
+        assert_eq!((size, size_is_lower_bound), (12, false));
+        let stored = bodies.read(&id, 0, 100).await.unwrap();
+        assert_eq!(text(&stored), "aaaabbbbcccc");
+        assert_eq!(stored.content_type.as_deref(), Some("text/plain"));
+    }
+}
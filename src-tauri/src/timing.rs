@@ -0,0 +1,306 @@
+use crate::dns::DnsLookup;
+use crate::tls::Handshake;
+use hyper_util::client::legacy::connect::Connection;
+use serde::Serialize;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tower_layer::Layer;
+use tower_service::Service;
+
+/// Per-phase breakdown of a request, in the spirit of curl's `-w` timings.
+/// The connection phases are absent when a pooled connection was reused.
+#[derive(Serialize, Default)]
+pub struct Timings {
+    pub dns_ms: Option<f64>,
+    pub connect_ms: Option<f64>,
+    pub tls_ms: Option<f64>,
+    /// From the connection being ready (or the send, when reused) to the
+    /// response head arriving.
+    pub ttfb_ms: f64,
+    /// From the response head to the last body byte.
+    pub download_ms: f64,
+    pub total_ms: f64,
+    /// Size of the request head and body as HTTP/1.1 would frame them;
+    /// TLS records and HTTP/2 framing are not counted.
+    pub bytes_sent: u64,
+    /// Size of the response head and body as received, same caveats.
+    pub bytes_received: u64,
+}
+
+/// Timestamps and handshake details reported by the resolver, connector and
+/// TLS hooks while the current request opens a connection.
+#[derive(Default)]
+pub struct ConnectionMarks {
+    pub connect_start: Option<Instant>,
+    pub dns_start: Option<Instant>,
+    pub dns_end: Option<Instant>,
+    pub tls_start: Option<Instant>,
+    pub connect_end: Option<Instant>,
+    /// Whether ALPN settled on `h2`, as the connector reported it.
+    pub negotiated_h2: bool,
+    pub dns: Option<DnsLookup>,
+    pub handshake: Handshake,
+}
+
+tokio::task_local! {
+    static MARKS: Arc<Mutex<ConnectionMarks>>;
+}
+
+/// Updates the marks of the request running on this task. Connections
+/// opened in the background (outside any instrumented request) are ignored.
+pub fn record(update: impl FnOnce(&mut ConnectionMarks)) {
+    let _ = MARKS.try_with(|marks| update(&mut marks.lock().expect("timing lock poisoned")));
+}
+
+/// Runs the future with connection instrumentation enabled and returns the
+/// marks that were recorded along the way.
+pub async fn instrument<F: Future>(future: F) -> (F::Output, ConnectionMarks) {
+    let marks = Arc::new(Mutex::new(ConnectionMarks::default()));
+    let output = MARKS.scope(marks.clone(), future).await;
+    let marks = std::mem::take(&mut *marks.lock().expect("timing lock poisoned"));
+    (output, marks)
+}
+
+impl Timings {
+    /// Derives the phase durations from the recorded instants. A connection
+    /// counts as new only if the connector finished on this request.
+    pub fn new(
+        marks: &ConnectionMarks,
+        start: Instant,
+        headers_received: Instant,
+        finished: Instant,
+    ) -> Self {
+        let mut timings = Timings {
+            ttfb_ms: millis(headers_received - start),
+            download_ms: millis(finished - headers_received),
+            total_ms: millis(finished - start),
+            ..Timings::default()
+        };
+
+        if let (Some(connect_start), Some(connect_end)) = (marks.connect_start, marks.connect_end) {
+            let tcp_start = match (marks.dns_start, marks.dns_end) {
+                (Some(dns_start), Some(dns_end)) => {
+                    timings.dns_ms = Some(millis(dns_end - dns_start));
+                    dns_end
+                }
+                _ => connect_start,
+            };
+            let tcp_end = match marks.tls_start {
+                Some(tls_start) => {
+                    timings.tls_ms = Some(millis(connect_end - tls_start));
+                    tls_start
+                }
+                None => connect_end,
+            };
+            timings.connect_ms = Some(millis(tcp_end.saturating_duration_since(tcp_start)));
+            timings.ttfb_ms = millis(headers_received.saturating_duration_since(connect_end));
+        }
+
+        timings
+    }
+}
+
+/// A duration in fractional milliseconds, as the timings report them.
+pub fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Size of the request as an HTTP/1.1 request line, headers and body.
+pub fn request_size(request: &reqwest::Request) -> u64 {
+    let url = request.url();
+    let target = url.path().len() + url.query().map_or(0, |query| query.len() + 1);
+    let request_line = request.method().as_str().len() + target + " HTTP/1.1\r\n".len() + 1;
+    let port = url.port().map_or(0, |port| port.to_string().len() + 1);
+    let host = url
+        .host_str()
+        .map_or(0, |host| "host: \r\n".len() + host.len() + port);
+    // Streamed bodies (files, multipart) are measured by their declared length
+    let body = match request.body().and_then(|body| body.as_bytes()) {
+        Some(bytes) => bytes.len(),
+        None => request
+            .headers()
+            .get(reqwest::header::CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok()?.parse().ok())
+            .unwrap_or(0),
+    };
+    (request_line + host + headers_size(request.headers()) + body) as u64
+}
+
+/// Size of the response status line and headers as HTTP/1.1 would send them.
+pub fn response_head_size(response: &reqwest::Response) -> u64 {
+    let status = response.status();
+    let reason = status.canonical_reason().unwrap_or_default();
+    let status_line = "HTTP/1.1 000 \r\n".len() + reason.len();
+    (status_line + headers_size(response.headers())) as u64
+}
+
+fn headers_size(headers: &reqwest::header::HeaderMap) -> usize {
+    let fields: usize = headers
+        .iter()
+        .map(|(name, value)| name.as_str().len() + ": \r\n".len() + value.len())
+        .sum();
+    fields + "\r\n".len()
+}
+
+/// Connector layer that marks when a new connection starts and when it is
+/// ready, TLS included, and whether it negotiated HTTP/2.
+#[derive(Clone)]
+pub struct ConnectTimingLayer;
+
+impl<S> Layer<S> for ConnectTimingLayer {
+    type Service = ConnectTiming<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ConnectTiming { inner }
+    }
+}
+
+#[derive(Clone)]
+pub struct ConnectTiming<S> {
+    inner: S,
+}
+
+impl<S, R> Service<R> for ConnectTiming<S>
+where
+    S: Service<R>,
+    S::Response: Connection,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<S::Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: R) -> Self::Future {
+        record(|marks| marks.connect_start = Some(Instant::now()));
+        let connecting = self.inner.call(request);
+        Box::pin(async move {
+            let result = connecting.await;
+            record(|marks| {
+                marks.connect_end = Some(Instant::now());
+                marks.negotiated_h2 = result
+                    .as_ref()
+                    .is_ok_and(|conn| conn.connected().is_negotiated_h2());
+            });
+            result
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_ms(actual: Option<f64>, expected: Option<f64>) {
+        match (actual, expected) {
+            (Some(actual), Some(expected)) => {
+                assert!(
+                    (actual - expected).abs() < 1e-6,
+                    "{} != {}",
+                    actual,
+                    expected
+                )
+            }
+            _ => assert_eq!(actual, expected),
+        }
+    }
+
+    /// Timings for a request sent at 0 ms whose head arrived at 100 ms and
+    /// body ended at 150 ms, with connection marks at the given offsets.
+    fn timings(marks: impl FnOnce(&mut ConnectionMarks, &dyn Fn(u64) -> Instant)) -> Timings {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut recorded = ConnectionMarks::default();
+        marks(&mut recorded, &at);
+        Timings::new(&recorded, start, at(100), at(150))
+    }
+
+    #[test]
+    fn reused_connections_only_have_request_phases() {
+        let timings = timings(|_, _| {});
+        assert_ms(timings.dns_ms, None);
+        assert_ms(timings.connect_ms, None);
+        assert_ms(timings.tls_ms, None);
+        assert_ms(Some(timings.ttfb_ms), Some(100.0));
+        assert_ms(Some(timings.download_ms), Some(50.0));
+        assert_ms(Some(timings.total_ms), Some(150.0));
+    }
+
+    #[test]
+    fn an_unfinished_connect_counts_as_reused() {
+        // A connection opened for another request is not this one's
+        let timings = timings(|marks, at| marks.connect_start = Some(at(1)));
+        assert_ms(timings.connect_ms, None);
+        assert_ms(Some(timings.ttfb_ms), Some(100.0));
+    }
+
+    #[test]
+    fn new_connections_are_split_into_phases() {
+        let timings = timings(|marks, at| {
+            marks.connect_start = Some(at(0));
+            marks.dns_start = Some(at(2));
+            marks.dns_end = Some(at(10));
+            marks.tls_start = Some(at(30));
+            marks.connect_end = Some(at(60));
+        });
+        assert_ms(timings.dns_ms, Some(8.0));
+        assert_ms(timings.connect_ms, Some(20.0));
+        assert_ms(timings.tls_ms, Some(30.0));
+        // Waiting starts once the connection is ready
+        assert_ms(Some(timings.ttfb_ms), Some(40.0));
+        assert_ms(Some(timings.total_ms), Some(150.0));
+    }
+
+    #[test]
+    fn plain_connections_without_a_lookup_are_all_connect() {
+        let timings = timings(|marks, at| {
+            marks.connect_start = Some(at(5));
+            marks.connect_end = Some(at(25));
+        });
+        assert_ms(timings.dns_ms, None);
+        assert_ms(timings.tls_ms, None);
+        assert_ms(timings.connect_ms, Some(20.0));
+        assert_ms(Some(timings.ttfb_ms), Some(75.0));
+    }
+
+    #[test]
+    fn request_size_counts_the_http1_framing() {
+        let url = url::Url::parse("http://example.test:8080/a?b=1").unwrap();
+        let mut request = reqwest::Request::new(reqwest::Method::POST, url);
+        request
+            .headers_mut()
+            .insert("x-test", "yz".parse().unwrap());
+        *request.body_mut() = Some("hello".into());
+        let framed = "POST /a?b=1 HTTP/1.1\r\nhost: example.test:8080\r\nx-test: yz\r\n\r\nhello";
+        assert_eq!(request_size(&request), framed.len() as u64);
+    }
+
+    #[test]
+    fn streamed_bodies_count_their_declared_length() {
+        let url = url::Url::parse("http://example.test/").unwrap();
+        let mut request = reqwest::Request::new(reqwest::Method::PUT, url);
+        request
+            .headers_mut()
+            .insert(reqwest::header::CONTENT_LENGTH, "1000".parse().unwrap());
+        let head = "PUT / HTTP/1.1\r\nhost: example.test\r\ncontent-length: 1000\r\n\r\n";
+        assert_eq!(request_size(&request), head.len() as u64 + 1000);
+    }
+
+    #[test]
+    fn response_head_size_counts_the_status_line_and_headers() {
+        let response = http::Response::builder()
+            .status(404)
+            .header("content-type", "text/plain")
+            .body("missing")
+            .unwrap();
+        let response = reqwest::Response::from(response);
+        let head = "HTTP/1.1 404 Not Found\r\ncontent-type: text/plain\r\n\r\n";
+        assert_eq!(response_head_size(&response), head.len() as u64);
+    }
+}
@@ -0,0 +1,395 @@
+use crate::certificates::{Certificate, TlsMaterial};
+use crate::error::{FetchError, FetchErrorKind};
+use crate::options::{HttpVersion, RequestOptions};
+use crate::timing;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::{
+    ClientSessionMemoryCache, ClientSessionStore, Resumption, Tls12ClientSessionValue,
+    Tls13ClientSessionValue, WebPkiServerVerifier,
+};
+use rustls::crypto::cipher::{
+    AeadKey, Iv, KeyBlockShape, MessageDecrypter, MessageEncrypter, Tls12AeadAlgorithm,
+    Tls13AeadAlgorithm, UnsupportedOperationError,
+};
+use rustls::crypto::{CryptoProvider, WebPkiSupportedAlgorithms};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{
+    CipherSuite, CipherSuiteCommon, ConnectionTrafficSecrets, DigitallySignedStruct, NamedGroup,
+    ProtocolVersion, RootCertStore, SignatureScheme, SupportedCipherSuite, Tls12CipherSuite,
+    Tls13CipherSuite,
+};
+use std::sync::{Arc, OnceLock};
+use std::time::Instant;
+
+/// What the handshake of the current request's connection revealed, as
+/// reported by the hooks in this module.
+#[derive(Default, Clone)]
+pub struct Handshake {
+    pub server_name: Option<String>,
+    pub version: Option<ProtocolVersion>,
+    pub cipher_suite: Option<CipherSuite>,
+    /// The certificates the server presented, leaf first. Empty when the
+    /// session was resumed, since the server then sends none.
+    pub peer_chain: Vec<CertificateDer<'static>>,
+}
+
+/// Builds the rustls configuration for a client. We configure rustls
+/// ourselves rather than through reqwest so the handshake can be observed.
+/// CA bundles in `certificates` are trusted in addition to the built-in
+/// roots, and a client identity among them is offered to the server.
+pub fn client_config(
+    options: &RequestOptions,
+    certificates: &[Certificate],
+) -> Result<rustls::ClientConfig, FetchError> {
+    let provider = provider();
+    let builder = rustls::ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(|e| FetchError::with_source(FetchErrorKind::ClientBuild, &e))?;
+
+    let mut roots = RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    };
+    let mut identity = None;
+    for certificate in certificates {
+        match certificate.material.parse()? {
+            TlsMaterial::Roots(certs) => {
+                for cert in certs {
+                    roots.add(cert).map_err(|e| {
+                        FetchError::with_source(FetchErrorKind::InvalidCertificate, &e)
+                    })?;
+                }
+            }
+            TlsMaterial::Identity(chain, key) => identity = Some((chain, key)),
+        }
+    }
+
+    let verifier: Arc<dyn ServerCertVerifier> = if options.accept_invalid_certs {
+        Arc::new(NoVerifier::new(&provider))
+    } else {
+        WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider)
+            .build()
+            .map_err(|e| FetchError::with_source(FetchErrorKind::ClientBuild, &e))?
+    };
+    let builder = builder
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(RecordingVerifier { inner: verifier }));
+    let mut config = match identity {
+        Some((chain, key)) => builder.with_client_auth_cert(chain, key).map_err(|e| {
+            FetchError::with_context(
+                FetchErrorKind::InvalidCertificate,
+                "Client certificate and key do not fit together",
+                &e,
+            )
+        })?,
+        None => builder.with_no_client_auth(),
+    };
+
+    config.resumption = Resumption::store(Arc::new(TimedSessionStore::default()));
+    config.alpn_protocols = match options.http_version {
+        HttpVersion::Auto => vec![b"h2".to_vec(), b"http/1.1".to_vec()],
+        HttpVersion::Http1 => vec![b"http/1.1".to_vec()],
+        HttpVersion::Http2 => vec![b"h2".to_vec()],
+        HttpVersion::Http3 => vec![b"h3".to_vec()],
+    };
+
+    Ok(config)
+}
+
+/// The ring provider with every cipher suite wrapped so the negotiated one
+/// is reported. Built once, as the wrappers live for the whole process.
+fn provider() -> Arc<CryptoProvider> {
+    static PROVIDER: OnceLock<Arc<CryptoProvider>> = OnceLock::new();
+    PROVIDER
+        .get_or_init(|| {
+            let mut provider = rustls::crypto::ring::default_provider();
+            provider.cipher_suites = provider
+                .cipher_suites
+                .into_iter()
+                .map(observed_suite)
+                .collect();
+            Arc::new(provider)
+        })
+        .clone()
+}
+
+/// Copies a cipher suite with its AEAD wrapped in `Observed`. rustls asks
+/// the AEAD for an encrypter once keys are agreed, which is when we learn
+/// which suite and protocol version the server picked.
+fn observed_suite(suite: SupportedCipherSuite) -> SupportedCipherSuite {
+    match suite {
+        SupportedCipherSuite::Tls13(inner) => {
+            SupportedCipherSuite::Tls13(Box::leak(Box::new(Tls13CipherSuite {
+                common: copy_common(&inner.common),
+                hkdf_provider: inner.hkdf_provider,
+                aead_alg: Box::leak(Box::new(Observed {
+                    inner: inner.aead_alg,
+                    suite: inner.common.suite,
+                })),
+                quic: inner.quic,
+            })))
+        }
+        SupportedCipherSuite::Tls12(inner) => {
+            SupportedCipherSuite::Tls12(Box::leak(Box::new(Tls12CipherSuite {
+                common: copy_common(&inner.common),
+                prf_provider: inner.prf_provider,
+                kx: inner.kx,
+                sign: inner.sign,
+                aead_alg: Box::leak(Box::new(Observed {
+                    inner: inner.aead_alg,
+                    suite: inner.common.suite,
+                })),
+            })))
+        }
+    }
+}
+
+fn copy_common(common: &CipherSuiteCommon) -> CipherSuiteCommon {
+    CipherSuiteCommon {
+        suite: common.suite,
+        hash_provider: common.hash_provider,
+        confidentiality_limit: common.confidentiality_limit,
+    }
+}
+
+/// An AEAD that records its cipher suite whenever keys are installed and
+/// otherwise defers to the real implementation.
+struct Observed<T: ?Sized + 'static> {
+    inner: &'static T,
+    suite: CipherSuite,
+}
+
+impl<T: ?Sized> Observed<T> {
+    fn record(&self, version: ProtocolVersion) {
+        timing::record(|marks| {
+            marks.handshake.version = Some(version);
+            marks.handshake.cipher_suite = Some(self.suite);
+        });
+    }
+}
+
+impl Tls13AeadAlgorithm for Observed<dyn Tls13AeadAlgorithm> {
+    fn encrypter(&self, key: AeadKey, iv: Iv) -> Box<dyn MessageEncrypter> {
+        self.record(ProtocolVersion::TLSv1_3);
+        self.inner.encrypter(key, iv)
+    }
+
+    fn decrypter(&self, key: AeadKey, iv: Iv) -> Box<dyn MessageDecrypter> {
+        self.inner.decrypter(key, iv)
+    }
+
+    fn key_len(&self) -> usize {
+        self.inner.key_len()
+    }
+
+    fn extract_keys(
+        &self,
+        key: AeadKey,
+        iv: Iv,
+    ) -> Result<ConnectionTrafficSecrets, UnsupportedOperationError> {
+        self.inner.extract_keys(key, iv)
+    }
+
+    fn fips(&self) -> bool {
+        self.inner.fips()
+    }
+}
+
+impl Tls12AeadAlgorithm for Observed<dyn Tls12AeadAlgorithm> {
+    fn encrypter(&self, key: AeadKey, iv: &[u8], extra: &[u8]) -> Box<dyn MessageEncrypter> {
+        self.record(ProtocolVersion::TLSv1_2);
+        self.inner.encrypter(key, iv, extra)
+    }
+
+    fn decrypter(&self, key: AeadKey, iv: &[u8]) -> Box<dyn MessageDecrypter> {
+        self.inner.decrypter(key, iv)
+    }
+
+    fn key_block_shape(&self) -> KeyBlockShape {
+        self.inner.key_block_shape()
+    }
+
+    fn extract_keys(
+        &self,
+        key: AeadKey,
+        iv: &[u8],
+        explicit: &[u8],
+    ) -> Result<ConnectionTrafficSecrets, UnsupportedOperationError> {
+        self.inner.extract_keys(key, iv, explicit)
+    }
+
+    fn fips(&self) -> bool {
+        self.inner.fips()
+    }
+}
+
+/// Session cache that marks the start of each handshake and the server it
+/// is with. rustls looks up resumption tickets right before sending the
+/// ClientHello, which is the moment the TCP connection is up and TLS begins.
+struct TimedSessionStore {
+    inner: ClientSessionMemoryCache,
+}
+
+impl Default for TimedSessionStore {
+    fn default() -> Self {
+        TimedSessionStore {
+            inner: ClientSessionMemoryCache::new(256),
+        }
+    }
+}
+
+impl std::fmt::Debug for TimedSessionStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TimedSessionStore").finish_non_exhaustive()
+    }
+}
+
+impl ClientSessionStore for TimedSessionStore {
+    fn set_kx_hint(&self, server_name: ServerName<'static>, group: NamedGroup) {
+        self.inner.set_kx_hint(server_name, group)
+    }
+
+    fn kx_hint(&self, server_name: &ServerName<'_>) -> Option<NamedGroup> {
+        self.inner.kx_hint(server_name)
+    }
+
+    fn set_tls12_session(&self, server_name: ServerName<'static>, value: Tls12ClientSessionValue) {
+        self.inner.set_tls12_session(server_name, value)
+    }
+
+    fn tls12_session(&self, server_name: &ServerName<'_>) -> Option<Tls12ClientSessionValue> {
+        self.inner.tls12_session(server_name)
+    }
+
+    fn remove_tls12_session(&self, server_name: &ServerName<'static>) {
+        self.inner.remove_tls12_session(server_name)
+    }
+
+    fn insert_tls13_ticket(
+        &self,
+        server_name: ServerName<'static>,
+        value: Tls13ClientSessionValue,
+    ) {
+        self.inner.insert_tls13_ticket(server_name, value)
+    }
+
+    fn take_tls13_ticket(
+        &self,
+        server_name: &ServerName<'static>,
+    ) -> Option<Tls13ClientSessionValue> {
+        timing::record(|marks| {
+            marks.tls_start = Some(Instant::now());
+            marks.handshake = Handshake {
+                server_name: Some(server_name.to_str().into_owned()),
+                ..Handshake::default()
+            };
+        });
+        self.inner.take_tls13_ticket(server_name)
+    }
+}
+
+/// Verifier wrapper that records the chain the server presented, whether
+/// or not it then passes verification.
+#[derive(Debug)]
+struct RecordingVerifier {
+    inner: Arc<dyn ServerCertVerifier>,
+}
+
+impl ServerCertVerifier for RecordingVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        timing::record(|marks| {
+            marks.handshake.peer_chain = std::iter::once(end_entity)
+                .chain(intermediates)
+                .map(|cert| cert.clone().into_owned())
+                .collect();
+        });
+        self.inner
+            .verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+
+    fn requires_raw_public_keys(&self) -> bool {
+        self.inner.requires_raw_public_keys()
+    }
+
+    fn root_hint_subjects(&self) -> Option<&[rustls::DistinguishedName]> {
+        self.inner.root_hint_subjects()
+    }
+}
+
+/// Verifier behind `accept_invalid_certs`: accepts any certificate but still
+/// checks handshake signatures so the connection itself is sound.
+#[derive(Debug)]
+struct NoVerifier {
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl NoVerifier {
+    fn new(provider: &CryptoProvider) -> Self {
+        NoVerifier {
+            algorithms: provider.signature_verification_algorithms,
+        }
+    }
+}
+
+impl ServerCertVerifier for NoVerifier {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
@@ -1,5 +1,5 @@
 import { useState, useEffect, useRef } from "react";
-import { invoke } from "@tauri-apps/api/core";
+import { invoke, Channel } from "@tauri-apps/api/core";
 import { save } from "@tauri-apps/plugin-dialog";
 import { writeTextFile } from "@tauri-apps/plugin-fs";
 import JsonView from "@uiw/react-json-view";
@@ -9,11 +9,227 @@ import "./App.css";
 type ThemeMode = "auto" | "light" | "dark";
 type KeyValuePair = { key: string; value: string };
 
+type ResponseBody = {
+  content_type: string | null;
+  charset: string | null;
+  size_bytes: number;
+  size_is_lower_bound: boolean;
+  truncated: boolean;
+} & (
+  | { kind: "empty" }
+  | { kind: "json"; value: any }
+  | { kind: "text"; text: string }
+  | { kind: "binary"; base64: string }
+);
+
+type Timings = {
+  dns_ms: number | null;
+  connect_ms: number | null;
+  tls_ms: number | null;
+  ttfb_ms: number;
+  download_ms: number;
+  total_ms: number;
+  bytes_sent: number;
+  bytes_received: number;
+};
+
+type HeaderEntry = {
+  name: string;
+  value: string;
+  raw_base64?: string;
+};
+
+type PeerCertificate = {
+  subject: string;
+  issuer: string;
+  subject_alt_names: string[];
+  serial_number: string;
+  not_before: number;
+  not_after: number;
+  sha256_fingerprint: string;
+};
+
+type DnsLookup = {
+  host: string;
+  source: "override" | "system" | "server";
+  server: string | null;
+  addresses: string[];
+};
+
+type ConnectionInfo = {
+  http_version: string;
+  remote_ip: string | null;
+  remote_port: number | null;
+  unix_socket: string | null;
+  // Unknown for HTTP/3
+  reused: boolean | null;
+  // Only for new connections to a host name
+  dns: DnsLookup | null;
+  tls: {
+    version: string | null;
+    cipher_suite: string | null;
+    alpn_protocol: string | null;
+    peer_certificates: PeerCertificate[];
+  } | null;
+};
+
+type RedirectHop = {
+  method: string;
+  url: string;
+  status_code: number;
+  location: string;
+  headers: HeaderEntry[];
+  timings: Timings;
+  connection: ConnectionInfo;
+};
+
+type Attempt = {
+  status_code: number | null;
+  error: FetchError | null;
+  duration_ms: number;
+  delay_ms: number | null;
+  warnings: string[];
+};
+
+type HttpVersion = "auto" | "http1" | "http2" | "http3";
+
+type BodyEncoding = {
+  content_encoding: string | null;
+  decoded: boolean;
+  encoded_bytes: number;
+  decoded_bytes: number;
+};
+
 type ApiResponse = {
   status_code: number;
-  headers: Record<string, string>;
-  body: any;
+  headers: HeaderEntry[];
+  body: ResponseBody;
   duration_ms: number;
+  timings: Timings;
+  connection: ConnectionInfo;
+  request_id: string;
+  request_url: string;
+  final_url: string;
+  redirects: RedirectHop[];
+  attempts: Attempt[];
+  encoding: BodyEncoding;
+  saved_to: string | null;
+  // Set when the body went over the size limit and was kept on disk
+  stored_body: string | null;
+  warnings: string[];
+};
+
+// One-line summary of the timing phases, e.g. for a tooltip
+const describeTimings = (t: Timings): string => {
+  const phase = (label: string, ms: number | null) =>
+    ms === null ? null : `${label} ${ms.toFixed(1)} ms`;
+  return [
+    phase("DNS", t.dns_ms),
+    phase("Connect", t.connect_ms),
+    phase("TLS", t.tls_ms),
+    phase("TTFB", t.ttfb_ms),
+    phase("Download", t.download_ms),
+  ]
+    .filter((p) => p !== null)
+    .join(" · ") + ` · ${t.bytes_sent} B sent, ${t.bytes_received} B received`;
+};
+
+// Most of a stored body read_response_body returns at once; larger
+// bodies can only be saved
+const MAX_READ_BYTES = 16 * 1024 * 1024;
+
+type DownloadProgress = {
+  bytes: number;
+  total: number | null;
+  bytes_per_second: number;
+};
+
+const formatBytes = (bytes: number): string => {
+  const units = ["B", "KB", "MB", "GB"];
+  let value = bytes;
+  let unit = 0;
+  while (value >= 1024 && unit < units.length - 1) {
+    value /= 1024;
+    unit++;
+  }
+  return `${value.toFixed(unit === 0 ? 0 : 1)} ${units[unit]}`;
+};
+
+const describeProgress = (p: DownloadProgress): string =>
+  (p.total === null ? formatBytes(p.bytes) : `${formatBytes(p.bytes)} of ${formatBytes(p.total)}`) +
+  ` · ${formatBytes(p.bytes_per_second)}/s`;
+
+// Rows for the connection table in Request Details
+const connectionRows = (c: ConnectionInfo): [string, string][] => {
+  const rows: [string, string][] = [
+    ["Protocol", c.http_version],
+    c.unix_socket !== null
+      ? ["Unix socket", c.unix_socket]
+      : ["Remote address", c.remote_ip === null ? "unknown" : `${c.remote_ip}:${c.remote_port}`],
+    ["Connection", c.reused === null ? "unknown" : c.reused ? "reused" : "new"],
+  ];
+  if (c.dns) {
+    const source = c.dns.server ? `${c.dns.source} ${c.dns.server}` : c.dns.source;
+    rows.push(["DNS", `${c.dns.host} → ${c.dns.addresses.join(", ")} (${source})`]);
+  }
+  if (c.tls) {
+    rows.push(
+      ["TLS version", c.tls.version ?? "unknown"],
+      ["Cipher suite", c.tls.cipher_suite ?? "unknown"],
+      ["ALPN", c.tls.alpn_protocol ?? "unknown"],
+    );
+  }
+  return rows;
+};
+
+const formatUnixDate = (seconds: number): string => new Date(seconds * 1000).toLocaleString();
+
+// Render a response body as text for export and previews
+const bodyAsText = (body: ResponseBody): string => {
+  switch (body.kind) {
+    case "json":
+      return JSON.stringify(body.value, null, 2);
+    case "text":
+      return body.text;
+    case "binary":
+      return body.base64;
+    case "empty":
+      return "";
+  }
+};
+
+type JarCookie = {
+  name: string;
+  value: string;
+  domain: string;
+  host_only: boolean;
+  path: string;
+  expires: number | null;
+  secure: boolean;
+  http_only: boolean;
+};
+
+type FetchError = {
+  kind: string;
+  message: string;
+  source_chain: string[];
+  attempts: Attempt[];
+};
+
+// Format a backend FetchError (or any thrown value) for display
+const describeError = (err: unknown): string => {
+  if (typeof err === "object" && err !== null && "kind" in err) {
+    const fetchError = err as FetchError;
+    const cause = fetchError.source_chain[fetchError.source_chain.length - 1];
+    const message = cause ? `${fetchError.message} (${cause})` : fetchError.message;
+    const attempts = fetchError.attempts ?? [];
+    if (attempts.length < 2) return message;
+    const outcomes = attempts.map(
+      (a, i) => `#${i + 1} ${a.error ? a.error.kind : a.status_code} in ${a.duration_ms.toFixed(0)} ms`,
+    );
+    return `${message}. Attempts: ${outcomes.join(", ")}`;
+  }
+  return String(err);
 };
 
 type RequestDetails = {
@@ -83,6 +299,7 @@ function App() {
   const [requestDetails, setRequestDetails] = useState<RequestDetails | null>(null);
   const [loading, setLoading] = useState<boolean>(false);
   const [error, setError] = useState<string | null>(null);
+  const [downloadProgress, setDownloadProgress] = useState<DownloadProgress | null>(null);
   const [themeMode, setThemeMode] = useState<ThemeMode>("auto");
   const [systemPrefersDark, setSystemPrefersDark] = useState<boolean>(
     window.matchMedia("(prefers-color-scheme: dark)").matches
@@ -93,6 +310,17 @@ function App() {
   const [history, setHistory] = useState<HistoryEntry[]>(loadHistory());
   const [isHistorySidebarOpen, setIsHistorySidebarOpen] = useState<boolean>(false);
   const downloadMenuRef = useRef<HTMLDivElement>(null);
+  const activeRequestId = useRef<string | null>(null);
+  const [useCookieJar, setUseCookieJar] = useState<boolean>(true);
+  const [decompress, setDecompress] = useState<boolean>(true);
+  const [httpVersion, setHttpVersion] = useState<HttpVersion>("auto");
+  const [retries, setRetries] = useState<number>(0);
+  const [dnsOverrides, setDnsOverrides] = useState<KeyValuePair[]>([
+    { key: "", value: "" },
+  ]);
+  const [dnsServer, setDnsServer] = useState<string>("");
+  const [unixSocket, setUnixSocket] = useState<string>("");
+  const [jarCookies, setJarCookies] = useState<JarCookie[]>([]);
 
   // Close download menu when clicking outside
   useEffect(() => {
@@ -152,20 +380,9 @@ function App() {
     localStorage.setItem("theme-mode", nextMode);
   };
 
-  // Auto-switch tab when Request Body becomes unavailable
-  useEffect(() => {
-    if (requestActiveTab === "body" &&
-        method !== "POST" && method !== "PUT" && method !== "PATCH") {
-      setRequestActiveTab("query");
-    }
-  }, [method, requestActiveTab]);
-
   // Keyboard navigation for Request Builder tabs
   const handleRequestTabKeyDown = (e: React.KeyboardEvent) => {
-    const tabs = ["query", "headers"];
-    if (method === "POST" || method === "PUT" || method === "PATCH") {
-      tabs.push("body");
-    }
+    const tabs = ["query", "headers", "body", "connection"];
     const currentIndex = tabs.indexOf(requestActiveTab);
 
     if (e.key === "ArrowRight") {
@@ -179,7 +396,7 @@ function App() {
 
   // Keyboard navigation for Response tabs
   const handleResponseTabKeyDown = (e: React.KeyboardEvent) => {
-    const tabs = ["body", "headers", "details"];
+    const tabs = ["body", "headers", "cookies", "details"];
     const currentIndex = tabs.indexOf(responseActiveTab);
 
     if (e.key === "ArrowRight") {
@@ -191,6 +408,27 @@ function App() {
     }
   };
 
+  // Cookies in the jar for the host of the last request
+  const loadCookies = async (url: string) => {
+    try {
+      const domain = new URL(url).hostname;
+      setJarCookies(await invoke<JarCookie[]>("list_cookies", { domain }));
+    } catch {
+      setJarCookies([]);
+    }
+  };
+
+  const deleteCookie = async (cookie: JarCookie) => {
+    await invoke("delete_cookie", { domain: cookie.domain, path: cookie.path, name: cookie.name });
+    if (response) await loadCookies(response.request_url);
+  };
+
+  const clearCookies = async () => {
+    if (!response) return;
+    await invoke("clear_cookies", { domain: new URL(response.request_url).hostname });
+    await loadCookies(response.request_url);
+  };
+
   const addQueryParam = () => {
     setQueryParams([...queryParams, { key: "", value: "" }]);
   };
@@ -205,6 +443,20 @@ function App() {
     setQueryParams(updated);
   };
 
+  const addDnsOverride = () => {
+    setDnsOverrides([...dnsOverrides, { key: "", value: "" }]);
+  };
+
+  const removeDnsOverride = (index: number) => {
+    setDnsOverrides(dnsOverrides.filter((_, i) => i !== index));
+  };
+
+  const updateDnsOverride = (index: number, field: "key" | "value", value: string) => {
+    const updated = [...dnsOverrides];
+    updated[index][field] = value;
+    setDnsOverrides(updated);
+  };
+
   const addHeader = () => {
     setHeaders([...headers, { key: "", value: "" }]);
   };
@@ -255,12 +507,13 @@ function App() {
   const downloadAsJson = async (includeHeaders: boolean = false) => {
     if (!response) return;
 
+    const body = response.body.kind === "json" ? response.body.value : bodyAsText(response.body);
     const data = includeHeaders ? {
       statusCode: response.status_code,
       headers: response.headers,
-      body: response.body,
+      body,
       durationMs: response.duration_ms
-    } : response.body;
+    } : body;
 
     const json = JSON.stringify(data, null, 2);
 
@@ -294,13 +547,13 @@ function App() {
       content += `Status: ${response.status_code}\n`;
       content += `Duration: ${response.duration_ms}ms\n\n`;
       content += "Headers:\n";
-      Object.entries(response.headers).forEach(([key, value]) => {
-        content += `${key}: ${value}\n`;
+      response.headers.forEach(({ name, value }) => {
+        content += `${name}: ${value}\n`;
       });
       content += "\nBody:\n";
     }
 
-    content += JSON.stringify(response.body, null, 2);
+    content += bodyAsText(response.body);
 
     try {
       const filePath = await save({
@@ -475,7 +728,8 @@ function App() {
     );
   };
 
-  async function fetchJson() {
+  // Send the request; with `saveTo` the body is streamed to that file
+  async function fetchJson(saveTo?: string) {
     if (!apiUrl.trim()) {
       setError("Please enter a URL");
       return;
@@ -483,8 +737,12 @@ function App() {
 
     setLoading(true);
     setError(null);
+    if (response?.stored_body) {
+      await invoke<boolean>("discard_response_body", { id: response.stored_body });
+    }
     setResponse(null);
     setRequestDetails(null);
+    setDownloadProgress(null);
 
     try {
       // Filter out empty query params and convert to tuple array
@@ -500,36 +758,34 @@ function App() {
           return acc;
         }, {} as Record<string, string>);
 
-      // Convert body params to JSON object (only for POST/PUT/PATCH)
+      // Convert body params to JSON object; any method may carry a body
       let body = null;
-      if (method === "POST" || method === "PUT" || method === "PATCH") {
-        if (bodyMode === "raw") {
-          // Use raw JSON mode
-          if (rawJsonBody.trim()) {
+      if (bodyMode === "raw") {
+        // Use raw JSON mode
+        if (rawJsonBody.trim()) {
+          try {
+            body = JSON.parse(rawJsonBody);
+            setJsonError(null);
+          } catch (e) {
+            setJsonError("Invalid JSON: " + (e as Error).message);
+            setError("Invalid JSON in request body");
+            setLoading(false);
+            return;
+          }
+        }
+      } else {
+        // Use form mode
+        const validBodyParams = bodyParams.filter((p) => p.key.trim() !== "");
+        if (validBodyParams.length > 0) {
+          body = validBodyParams.reduce((acc, p) => {
+            // Try to parse value as JSON, otherwise use as string
             try {
-              body = JSON.parse(rawJsonBody);
-              setJsonError(null);
-            } catch (e) {
-              setJsonError("Invalid JSON: " + (e as Error).message);
-              setError("Invalid JSON in request body");
-              setLoading(false);
-              return;
+              acc[p.key] = JSON.parse(p.value);
+            } catch {
+              acc[p.key] = p.value;
             }
-          }
-        } else {
-          // Use form mode
-          const validBodyParams = bodyParams.filter((p) => p.key.trim() !== "");
-          if (validBodyParams.length > 0) {
-            body = validBodyParams.reduce((acc, p) => {
-              // Try to parse value as JSON, otherwise use as string
-              try {
-                acc[p.key] = JSON.parse(p.value);
-              } catch {
-                acc[p.key] = p.value;
-              }
-              return acc;
-            }, {} as Record<string, any>);
-          }
+            return acc;
+          }, {} as Record<string, any>);
         }
       }
 
@@ -542,14 +798,46 @@ function App() {
         body,
       });
 
-      const data = await invoke<ApiResponse>("fetch_json", {
+      const requestId = crypto.randomUUID();
+      activeRequestId.current = requestId;
+      const args = {
         url: apiUrl,
         method: method,
-        headers: Object.keys(validHeaders).length > 0 ? validHeaders : null,
+        headers: headers
+          .filter((h) => h.key.trim() !== "")
+          .map((h) => ({ name: h.key, value: h.value, enabled: true })),
         queryParams: validQueryParams.length > 0 ? validQueryParams : null,
-        body: body,
-      });
+        body: body !== null ? { type: "json", value: body } : null,
+        options: {
+          use_cookie_jar: useCookieJar,
+          decompress,
+          http_version: httpVersion,
+          retry: { max_attempts: retries + 1 },
+          dns_overrides: dnsOverrides
+            .filter((o) => o.key.trim() !== "")
+            .map((o) => ({
+              host: o.key.trim(),
+              addresses: o.value.split(",").map((a) => a.trim()).filter((a) => a !== ""),
+            })),
+          dns_server: dnsServer.trim() !== "" ? dnsServer.trim() : null,
+          unix_socket: unixSocket.trim() !== "" ? unixSocket.trim() : null,
+        },
+        requestId,
+      };
+      let data: ApiResponse;
+      if (saveTo) {
+        const onProgress = new Channel<DownloadProgress>();
+        onProgress.onmessage = setDownloadProgress;
+        data = await invoke<ApiResponse>("download_to_file", {
+          ...args,
+          path: saveTo,
+          onProgress,
+        });
+      } else {
+        data = await invoke<ApiResponse>("fetch_json", args);
+      }
       setResponse(data);
+      await loadCookies(data.request_url);
 
       // Capture history
       const historyEntry: HistoryEntry = {
@@ -577,12 +865,59 @@ function App() {
       setHistory(updatedHistory);
       saveHistory(updatedHistory);
     } catch (err) {
-      setError(err as string);
+      setError(describeError(err));
     } finally {
+      activeRequestId.current = null;
       setLoading(false);
     }
   }
 
+  // Ask where to save the body, then send the request in download mode
+  async function downloadToFile() {
+    const fileName = apiUrl.split("?")[0].split("/").filter(Boolean).pop();
+    const filePath = await save({
+      title: "Save response body",
+      defaultPath: fileName && fileName.includes(".") ? fileName : undefined,
+    });
+    if (filePath) {
+      await fetchJson(filePath);
+    }
+  }
+
+  // Replace the preview of an oversized body with the whole of it
+  async function loadFullBody() {
+    if (!response?.stored_body) return;
+    try {
+      const body = await invoke<ResponseBody>("read_response_body", {
+        id: response.stored_body,
+        offset: 0,
+        length: response.body.size_bytes,
+      });
+      setResponse({ ...response, body });
+    } catch (err) {
+      setError(describeError(err));
+    }
+  }
+
+  // Save an oversized body that was kept on disk
+  async function saveStoredBody() {
+    if (!response?.stored_body) return;
+    const filePath = await save({ title: "Save response body" });
+    if (!filePath) return;
+    try {
+      await invoke("save_response_body", { id: response.stored_body, path: filePath });
+    } catch (err) {
+      setError(describeError(err));
+    }
+  }
+
+  // Abort the request that is currently in flight
+  async function cancelRequest() {
+    if (activeRequestId.current) {
+      await invoke<boolean>("cancel_request", { id: activeRequestId.current });
+    }
+  }
+
   return (
     <main className="container">
       {isHistorySidebarOpen && (
@@ -623,20 +958,23 @@ function App() {
 
       <div className="request-builder">
         <div className="method-url-row">
-          <select
+          <input
+            type="text"
             className="method-select"
+            list="http-methods"
+            aria-label="HTTP method"
+            spellCheck={false}
             value={method}
             onChange={(e) => setMethod(e.target.value)}
             disabled={loading}
-          >
-            <option value="GET">GET</option>
-            <option value="POST">POST</option>
-            <option value="PUT">PUT</option>
-            <option value="PATCH">PATCH</option>
-            <option value="DELETE">DELETE</option>
-            <option value="HEAD">HEAD</option>
-            <option value="OPTIONS">OPTIONS</option>
-          </select>
+          />
+          <datalist id="http-methods">
+            {["GET", "POST", "PUT", "PATCH", "DELETE", "HEAD", "OPTIONS", "TRACE",
+              "QUERY", "PURGE", "PROPFIND", "PROPPATCH", "MKCOL", "COPY", "MOVE",
+              "LOCK", "UNLOCK", "REPORT"].map((m) => (
+              <option key={m} value={m} />
+            ))}
+          </datalist>
           <input
             type="text"
             className="url-input"
@@ -645,9 +983,64 @@ function App() {
             placeholder="Enter API URL (e.g., https://api.github.com/users/github)"
             disabled={loading}
           />
-          <button onClick={fetchJson} disabled={loading}>
-            {loading ? "Fetching..." : "Send"}
-          </button>
+          <label className="cookie-toggle" title="Send and store cookies using the cookie jar">
+            <input
+              type="checkbox"
+              checked={useCookieJar}
+              onChange={(e) => setUseCookieJar(e.target.checked)}
+              disabled={loading}
+            />
+            Cookies
+          </label>
+          <label className="cookie-toggle" title="Decode compressed bodies; off shows the raw bytes as received">
+            <input
+              type="checkbox"
+              checked={decompress}
+              onChange={(e) => setDecompress(e.target.checked)}
+              disabled={loading}
+            />
+            Decompress
+          </label>
+          <select
+            className="protocol-select"
+            aria-label="HTTP version"
+            title="HTTP/2 uses prior knowledge (h2c over plain HTTP); HTTP/3 needs an https URL"
+            value={httpVersion}
+            onChange={(e) => setHttpVersion(e.target.value as HttpVersion)}
+            disabled={loading}
+          >
+            <option value="auto">Auto</option>
+            <option value="http1">HTTP/1.1</option>
+            <option value="http2">HTTP/2</option>
+            <option value="http3">HTTP/3</option>
+          </select>
+          <label className="cookie-toggle" title="Retry idempotent requests after network errors and 408, 429, 502, 503 or 504 responses">
+            Retries
+            <input
+              type="number"
+              className="retries-input"
+              min={0}
+              max={10}
+              value={retries}
+              onChange={(e) => setRetries(Math.min(10, Math.max(0, Number(e.target.value) || 0)))}
+              disabled={loading}
+            />
+          </label>
+          {loading ? (
+            <>
+              {downloadProgress && (
+                <span className="timing">{describeProgress(downloadProgress)}</span>
+              )}
+              <button onClick={cancelRequest}>Cancel</button>
+            </>
+          ) : (
+            <>
+              <button onClick={() => fetchJson()}>Send</button>
+              <button onClick={downloadToFile} title="Stream the response body to a file">
+                Download
+              </button>
+            </>
+          )}
         </div>
 
         <div className="section-container params-section">
@@ -679,20 +1072,31 @@ function App() {
                 Headers
               </button>
 
-              {(method === "POST" || method === "PUT" || method === "PATCH") && (
-                <button
-                  role="tab"
-                  aria-selected={requestActiveTab === "body"}
-                  aria-controls="request-body-panel"
-                  id="request-body-tab"
-                  tabIndex={requestActiveTab === "body" ? 0 : -1}
-                  className={`tab-button ${requestActiveTab === "body" ? "active" : ""}`}
-                  onClick={() => setRequestActiveTab("body")}
-                  disabled={loading}
-                >
-                  Request Body
-                </button>
-              )}
+              <button
+                role="tab"
+                aria-selected={requestActiveTab === "body"}
+                aria-controls="request-body-panel"
+                id="request-body-tab"
+                tabIndex={requestActiveTab === "body" ? 0 : -1}
+                className={`tab-button ${requestActiveTab === "body" ? "active" : ""}`}
+                onClick={() => setRequestActiveTab("body")}
+                disabled={loading}
+              >
+                Request Body
+              </button>
+
+              <button
+                role="tab"
+                aria-selected={requestActiveTab === "connection"}
+                aria-controls="request-connection-panel"
+                id="request-connection-tab"
+                tabIndex={requestActiveTab === "connection" ? 0 : -1}
+                className={`tab-button ${requestActiveTab === "connection" ? "active" : ""}`}
+                onClick={() => setRequestActiveTab("connection")}
+                disabled={loading}
+              >
+                Connection
+              </button>
             </div>
 
             <div className="tab-content">
@@ -786,105 +1190,167 @@ function App() {
                 </div>
               </div>
 
-              {(method === "POST" || method === "PUT" || method === "PATCH") && (
-                <div
-                  role="tabpanel"
-                  id="request-body-panel"
-                  aria-labelledby="request-body-tab"
-                  hidden={requestActiveTab !== "body"}
-                  className="tab-panel"
-                >
-                  <div className="body-mode-toggle">
+              <div
+                role="tabpanel"
+                id="request-body-panel"
+                aria-labelledby="request-body-tab"
+                hidden={requestActiveTab !== "body"}
+                className="tab-panel"
+              >
+                <div className="body-mode-toggle">
+                  <button
+                    type="button"
+                    className={bodyMode === "form" ? "active" : ""}
+                    onClick={() => setBodyMode("form")}
+                    disabled={loading}
+                  >
+                    Form
+                  </button>
+                  <button
+                    type="button"
+                    className={bodyMode === "raw" ? "active" : ""}
+                    onClick={() => setBodyMode("raw")}
+                    disabled={loading}
+                  >
+                    Raw JSON
+                  </button>
+                </div>
+
+                {bodyMode === "form" ? (
+                  <div className="key-value-list">
+                    {bodyParams.map((param, index) => (
+                      <div key={index} className="key-value-row">
+                        <input
+                          type="text"
+                          placeholder="Key"
+                          value={param.key}
+                          onChange={(e) => updateBodyParam(index, "key", e.target.value)}
+                          disabled={loading}
+                        />
+                        <input
+                          type="text"
+                          placeholder="Value (can be JSON)"
+                          value={param.value}
+                          onChange={(e) => updateBodyParam(index, "value", e.target.value)}
+                          disabled={loading}
+                        />
+                        <button
+                          type="button"
+                          onClick={() => removeBodyParam(index)}
+                          disabled={loading}
+                          className="remove-btn"
+                        >
+                          ✕
+                        </button>
+                      </div>
+                    ))}
                     <button
                       type="button"
-                      className={bodyMode === "form" ? "active" : ""}
-                      onClick={() => setBodyMode("form")}
+                      onClick={addBodyParam}
                       disabled={loading}
+                      className="add-btn"
                     >
-                      Form
+                      + Add Field
                     </button>
+                  </div>
+                ) : (
+                  <div className="raw-json-editor">
+                    <textarea
+                      className="json-textarea"
+                      value={rawJsonBody}
+                      onChange={(e) => {
+                        setRawJsonBody(e.target.value);
+                        // Clear error when user starts typing
+                        if (jsonError) setJsonError(null);
+                      }}
+                      placeholder='{\n  "key": "value",\n  "nested": {\n    "example": true\n  }\n}'
+                      disabled={loading}
+                      spellCheck={false}
+                    />
+                    {jsonError && <div className="json-error">{jsonError}</div>}
                     <button
                       type="button"
-                      className={bodyMode === "raw" ? "active" : ""}
-                      onClick={() => setBodyMode("raw")}
-                      disabled={loading}
+                      className="format-btn"
+                      onClick={() => {
+                        try {
+                          const parsed = JSON.parse(rawJsonBody);
+                          setRawJsonBody(JSON.stringify(parsed, null, 2));
+                          setJsonError(null);
+                        } catch (e) {
+                          setJsonError("Invalid JSON: " + (e as Error).message);
+                        }
+                      }}
+                      disabled={loading || !rawJsonBody.trim()}
                     >
-                      Raw JSON
+                      Format JSON
                     </button>
                   </div>
-
-                  {bodyMode === "form" ? (
-                    <div className="key-value-list">
-                      {bodyParams.map((param, index) => (
-                        <div key={index} className="key-value-row">
-                          <input
-                            type="text"
-                            placeholder="Key"
-                            value={param.key}
-                            onChange={(e) => updateBodyParam(index, "key", e.target.value)}
-                            disabled={loading}
-                          />
-                          <input
-                            type="text"
-                            placeholder="Value (can be JSON)"
-                            value={param.value}
-                            onChange={(e) => updateBodyParam(index, "value", e.target.value)}
-                            disabled={loading}
-                          />
-                          <button
-                            type="button"
-                            onClick={() => removeBodyParam(index)}
-                            disabled={loading}
-                            className="remove-btn"
-                          >
-                            ✕
-                          </button>
-                        </div>
-                      ))}
-                      <button
-                        type="button"
-                        onClick={addBodyParam}
+                )}
+              </div>
+              <div
+                role="tabpanel"
+                id="request-connection-panel"
+                aria-labelledby="request-connection-tab"
+                hidden={requestActiveTab !== "connection"}
+                className="tab-panel"
+              >
+                <label className="connection-field">
+                  Unix socket
+                  <input
+                    type="text"
+                    placeholder="None (e.g., /var/run/docker.sock); the URL still gives the path and Host"
+                    value={unixSocket}
+                    onChange={(e) => setUnixSocket(e.target.value)}
+                    disabled={loading}
+                  />
+                </label>
+                <div className="key-value-list">
+                  {dnsOverrides.map((override, index) => (
+                    <div key={index} className="key-value-row">
+                      <input
+                        type="text"
+                        placeholder="Host (e.g., api.example.com)"
+                        value={override.key}
+                        onChange={(e) => updateDnsOverride(index, "key", e.target.value)}
                         disabled={loading}
-                        className="add-btn"
-                      >
-                        + Add Field
-                      </button>
-                    </div>
-                  ) : (
-                    <div className="raw-json-editor">
-                      <textarea
-                        className="json-textarea"
-                        value={rawJsonBody}
-                        onChange={(e) => {
-                          setRawJsonBody(e.target.value);
-                          // Clear error when user starts typing
-                          if (jsonError) setJsonError(null);
-                        }}
-                        placeholder='{\n  "key": "value",\n  "nested": {\n    "example": true\n  }\n}'
+                      />
+                      <input
+                        type="text"
+                        placeholder="Addresses (e.g., 127.0.0.1, ::1)"
+                        value={override.value}
+                        onChange={(e) => updateDnsOverride(index, "value", e.target.value)}
                         disabled={loading}
-                        spellCheck={false}
                       />
-                      {jsonError && <div className="json-error">{jsonError}</div>}
                       <button
                         type="button"
-                        className="format-btn"
-                        onClick={() => {
-                          try {
-                            const parsed = JSON.parse(rawJsonBody);
-                            setRawJsonBody(JSON.stringify(parsed, null, 2));
-                            setJsonError(null);
-                          } catch (e) {
-                            setJsonError("Invalid JSON: " + (e as Error).message);
-                          }
-                        }}
-                        disabled={loading || !rawJsonBody.trim()}
+                        onClick={() => removeDnsOverride(index)}
+                        disabled={loading}
+                        className="remove-btn"
                       >
-                        Format JSON
+                        ✕
                       </button>
                     </div>
-                  )}
+                  ))}
+                  <button
+                    type="button"
+                    onClick={addDnsOverride}
+                    disabled={loading}
+                    className="add-btn"
+                  >
+                    + Add Override
+                  </button>
                 </div>
-              )}
+                <label className="connection-field">
+                  DNS server
+                  <input
+                    type="text"
+                    placeholder="System resolver (or e.g., 1.1.1.1:53)"
+                    value={dnsServer}
+                    onChange={(e) => setDnsServer(e.target.value)}
+                    disabled={loading}
+                  />
+                </label>
+              </div>
             </div>
           </div>
         </div>
@@ -902,7 +1368,37 @@ function App() {
             <span className={`status-badge status-${Math.floor(response.status_code / 100)}xx`}>
               {response.status_code}
             </span>
-            <span className="timing">{response.duration_ms} ms</span>
+            <span className="timing" title={describeTimings(response.timings)}>{response.duration_ms} ms</span>
+            {response.saved_to && (
+              <span className="timing">
+                Saved {formatBytes(response.body.size_bytes)} to {response.saved_to}
+              </span>
+            )}
+            {response.encoding.content_encoding && (
+              <span className="timing" title={`Content-Encoding: ${response.encoding.content_encoding}`}>
+                {response.encoding.decoded
+                  ? `${response.encoding.content_encoding}: ${formatBytes(response.encoding.encoded_bytes)} → ${formatBytes(response.encoding.decoded_bytes)}`
+                  : `${response.encoding.content_encoding} (raw): ${formatBytes(response.encoding.encoded_bytes)}`}
+              </span>
+            )}
+            {response.body.truncated && (
+              <span className="response-warning">
+                Showing a preview of the start of the body (
+                {response.body.size_is_lower_bound ? "at least " : ""}
+                {formatBytes(response.body.size_bytes)} in total)
+              </span>
+            )}
+            {response.stored_body && (
+              <>
+                {response.body.truncated && response.body.size_bytes <= MAX_READ_BYTES && (
+                  <button className="add-btn" onClick={loadFullBody}>Load full body</button>
+                )}
+                <button className="add-btn" onClick={saveStoredBody}>Save body…</button>
+              </>
+            )}
+            {response.warnings.map((warning) => (
+              <span key={warning} className="response-warning">{warning}</span>
+            ))}
           </div>
 
           <div className="tab-container">
@@ -929,7 +1425,20 @@ function App() {
                 onClick={() => setResponseActiveTab("headers")}
               >
                 Response Headers
-                <span className="tab-badge">{Object.keys(response.headers).length}</span>
+                <span className="tab-badge">{response.headers.length}</span>
+              </button>
+
+              <button
+                role="tab"
+                aria-selected={responseActiveTab === "cookies"}
+                aria-controls="response-cookies-panel"
+                id="response-cookies-tab"
+                tabIndex={responseActiveTab === "cookies" ? 0 : -1}
+                className={`tab-button ${responseActiveTab === "cookies" ? "active" : ""}`}
+                onClick={() => setResponseActiveTab("cookies")}
+              >
+                Cookies
+                <span className="tab-badge">{jarCookies.length}</span>
               </button>
 
               <button
@@ -980,14 +1489,22 @@ function App() {
                     )}
                   </div>
                 </div>
-                <div className="json-viewer-container">
-                  <JsonView
-                    value={response.body}
-                    collapsed={2}
-                    displayDataTypes={false}
-                    style={isDarkMode ? darkTheme : undefined}
-                  />
-                </div>
+                {response.body.kind === "json" && typeof response.body.value === "object" && response.body.value !== null ? (
+                  <div className="json-viewer-container">
+                    <JsonView
+                      value={response.body.value}
+                      collapsed={2}
+                      displayDataTypes={false}
+                      style={isDarkMode ? darkTheme : undefined}
+                    />
+                  </div>
+                ) : response.body.kind === "binary" ? (
+                  <pre className="body-preview">
+                    Binary content ({response.body.content_type ?? "unknown type"}, {response.body.size_bytes} bytes)
+                  </pre>
+                ) : (
+                  <pre className="body-preview">{bodyAsText(response.body)}</pre>
+                )}
               </div>
 
               <div
@@ -1006,9 +1523,9 @@ function App() {
                       </tr>
                     </thead>
                     <tbody>
-                      {Object.entries(response.headers).map(([key, value]) => (
-                        <tr key={key}>
-                          <td className="header-key">{key}</td>
+                      {response.headers.map(({ name, value }, index) => (
+                        <tr key={`${name}-${index}`}>
+                          <td className="header-key">{name}</td>
                           <td className="header-value">{value}</td>
                         </tr>
                       ))}
@@ -1017,6 +1534,54 @@ function App() {
                 </div>
               </div>
 
+              <div
+                role="tabpanel"
+                id="response-cookies-panel"
+                aria-labelledby="response-cookies-tab"
+                hidden={responseActiveTab !== "cookies"}
+                className="tab-panel"
+              >
+                <div className="headers-display">
+                  <table>
+                    <thead>
+                      <tr>
+                        <th>Name</th>
+                        <th>Value</th>
+                        <th>Domain</th>
+                        <th>Path</th>
+                        <th>Expires</th>
+                        <th></th>
+                      </tr>
+                    </thead>
+                    <tbody>
+                      {jarCookies.map((cookie) => (
+                        <tr key={`${cookie.domain}${cookie.path}${cookie.name}`}>
+                          <td className="header-key">{cookie.name}</td>
+                          <td className="header-value">{cookie.value}</td>
+                          <td>{cookie.host_only ? cookie.domain : `.${cookie.domain}`}</td>
+                          <td>{cookie.path}</td>
+                          <td>
+                            {cookie.expires === null
+                              ? "Session"
+                              : new Date(cookie.expires * 1000).toLocaleString()}
+                          </td>
+                          <td>
+                            <button className="remove-btn" onClick={() => deleteCookie(cookie)}>
+                              ✕
+                            </button>
+                          </td>
+                        </tr>
+                      ))}
+                    </tbody>
+                  </table>
+                  {jarCookies.length > 0 && (
+                    <button className="add-btn" onClick={clearCookies}>
+                      Clear cookies for this host
+                    </button>
+                  )}
+                </div>
+              </div>
+
               <div
                 role="tabpanel"
                 id="request-details-panel"
@@ -1026,17 +1591,56 @@ function App() {
               >
                 <div className="request-display">
                   <div className="request-line">
-                    <strong>{requestDetails.method}</strong> {requestDetails.url}
-                    {requestDetails.queryParams.length > 0 && (
-                      <>
-                        ?
-                        {requestDetails.queryParams
-                          .map(([k, v]) => `${k}=${v}`)
-                          .join("&")}
-                      </>
-                    )}
+                    <strong>{requestDetails.method}</strong> {response.request_url}
                   </div>
 
+                  {response.redirects.length > 0 && (
+                    <>
+                      <h4>Redirects</h4>
+                      <table>
+                        <tbody>
+                          {response.redirects.map((hop, index) => (
+                            <tr key={index} title={describeTimings(hop.timings)}>
+                              <td className="header-key">
+                                {hop.status_code} {hop.method}
+                              </td>
+                              <td className="header-value">
+                                {hop.url} → {hop.location} ({hop.timings.total_ms.toFixed(1)} ms)
+                              </td>
+                            </tr>
+                          ))}
+                          <tr>
+                            <td className="header-key">{response.status_code}</td>
+                            <td className="header-value">{response.final_url}</td>
+                          </tr>
+                        </tbody>
+                      </table>
+                    </>
+                  )}
+
+                  {response.attempts.length > 1 && (
+                    <>
+                      <h4>Attempts</h4>
+                      <table>
+                        <tbody>
+                          {response.attempts.map((attempt, index) => (
+                            <tr key={index}>
+                              <td className="header-key">#{index + 1}</td>
+                              <td className="header-value">
+                                {attempt.error ? attempt.error.message : attempt.status_code} (
+                                {attempt.duration_ms.toFixed(1)} ms)
+                                {attempt.delay_ms !== null && `, retried after ${attempt.delay_ms.toFixed(0)} ms`}
+                                {attempt.warnings.map((warning) => (
+                                  <div key={warning} className="response-warning">{warning}</div>
+                                ))}
+                              </td>
+                            </tr>
+                          ))}
+                        </tbody>
+                      </table>
+                    </>
+                  )}
+
                   {Object.keys(requestDetails.headers).length > 0 && (
                     <>
                       <h4>Request Headers</h4>
@@ -1061,6 +1665,56 @@ function App() {
                       </pre>
                     </>
                   )}
+
+                  <h4>Connection</h4>
+                  <table>
+                    <tbody>
+                      {connectionRows(response.connection).map(([key, value]) => (
+                        <tr key={key}>
+                          <td className="header-key">{key}</td>
+                          <td className="header-value">{value}</td>
+                        </tr>
+                      ))}
+                    </tbody>
+                  </table>
+
+                  {response.connection.tls?.peer_certificates.map((cert, index) => (
+                    <div key={cert.sha256_fingerprint}>
+                      <h4>{index === 0 ? "Server Certificate" : `Chain Certificate ${index}`}</h4>
+                      <table>
+                        <tbody>
+                          <tr>
+                            <td className="header-key">Subject</td>
+                            <td className="header-value">{cert.subject}</td>
+                          </tr>
+                          <tr>
+                            <td className="header-key">Issuer</td>
+                            <td className="header-value">{cert.issuer}</td>
+                          </tr>
+                          {cert.subject_alt_names.length > 0 && (
+                            <tr>
+                              <td className="header-key">Alternative names</td>
+                              <td className="header-value">{cert.subject_alt_names.join(", ")}</td>
+                            </tr>
+                          )}
+                          <tr>
+                            <td className="header-key">Valid</td>
+                            <td className="header-value">
+                              {formatUnixDate(cert.not_before)} – {formatUnixDate(cert.not_after)}
+                            </td>
+                          </tr>
+                          <tr>
+                            <td className="header-key">Serial number</td>
+                            <td className="header-value">{cert.serial_number}</td>
+                          </tr>
+                          <tr>
+                            <td className="header-key">SHA-256</td>
+                            <td className="header-value">{cert.sha256_fingerprint}</td>
+                          </tr>
+                        </tbody>
+                      </table>
+                    </div>
+                  ))}
                 </div>
               </div>
             </div>